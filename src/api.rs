@@ -1,8 +1,16 @@
+use std::{
+    num::ParseIntError,
+    ops::RangeInclusive,
+    time::{Duration, SystemTime, TryFromFloatSecsError, UNIX_EPOCH},
+};
+
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::{IntoParams, ToSchema};
 
-use crate::{model, player};
+use crate::{auth, effects, emitter, model, output, player, scheduler};
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct Library {
     pub collections: Vec<Collection>,
 }
@@ -15,12 +23,14 @@ impl From<model::Library> for Library {
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct Collection {
     pub id: String,
     pub name: String,
     pub clips: Vec<Clip>,
     pub kind: model::CollectionKind,
+    #[schema(value_type = Vec<Object>)]
+    pub effects: Vec<model::Effect>,
 }
 
 impl From<model::Collection> for Collection {
@@ -30,14 +40,17 @@ impl From<model::Collection> for Collection {
             name: m.name,
             clips: m.clips.into_iter().map(|c| c.into()).collect(),
             kind: m.kind,
+            effects: m.effects,
         }
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct Clip {
     pub id: String,
     pub name: String,
+    pub layers: usize,
+    pub tags: Vec<String>,
 }
 
 impl From<model::Clip> for Clip {
@@ -45,6 +58,8 @@ impl From<model::Clip> for Clip {
         Clip {
             id: m.id.to_string(),
             name: m.name,
+            layers: m.layers.len(),
+            tags: m.tags,
         }
     }
 }
@@ -52,37 +67,840 @@ impl From<model::Clip> for Clip {
 #[derive(Serialize)]
 pub enum PlayerEvent {
     Started {
+        instance_id: String,
         coll_id: String,
         clip_id: String,
         duration: f64,
     },
     Stopped {
+        instance_id: String,
         coll_id: String,
         clip_id: String,
     },
+    Paused {
+        instance_id: String,
+        coll_id: String,
+        clip_id: String,
+    },
+    Resumed {
+        instance_id: String,
+        coll_id: String,
+        clip_id: String,
+    },
+    Looped {
+        instance_id: String,
+        coll_id: String,
+        clip_id: String,
+    },
+    GainChanged {
+        coll_id: String,
+        gain: f64,
+    },
+    IntensityChanged {
+        coll_id: String,
+        clip_id: String,
+        intensity: f64,
+    },
+    Error {
+        coll_id: String,
+        clip_id: String,
+        message: String,
+    },
+    LibraryChanged,
+    Scheduled(Scheduled),
+    Fired {
+        id: String,
+        error: Option<String>,
+    },
+    Cancelled {
+        id: String,
+    },
+    EmitterStarted {
+        id: String,
+        coll_id: String,
+    },
+    Emitted {
+        id: String,
+        coll_id: String,
+        clip_id: String,
+    },
+    EmitterStopped {
+        id: String,
+    },
 }
 
 impl From<player::PlayerEvent> for PlayerEvent {
     fn from(m: player::PlayerEvent) -> Self {
         match m {
             player::PlayerEvent::Started {
+                instance_id,
                 coll_id,
                 clip_id,
                 duration,
             } => PlayerEvent::Started {
+                instance_id: instance_id.to_string(),
                 coll_id: coll_id.to_string(),
                 clip_id: clip_id.to_string(),
                 duration,
             },
-            player::PlayerEvent::Stopped { coll_id, clip_id } => PlayerEvent::Stopped {
+            player::PlayerEvent::Stopped {
+                instance_id,
+                coll_id,
+                clip_id,
+            } => PlayerEvent::Stopped {
+                instance_id: instance_id.to_string(),
                 coll_id: coll_id.to_string(),
                 clip_id: clip_id.to_string(),
             },
+            player::PlayerEvent::Paused {
+                instance_id,
+                coll_id,
+                clip_id,
+            } => PlayerEvent::Paused {
+                instance_id: instance_id.to_string(),
+                coll_id: coll_id.to_string(),
+                clip_id: clip_id.to_string(),
+            },
+            player::PlayerEvent::Resumed {
+                instance_id,
+                coll_id,
+                clip_id,
+            } => PlayerEvent::Resumed {
+                instance_id: instance_id.to_string(),
+                coll_id: coll_id.to_string(),
+                clip_id: clip_id.to_string(),
+            },
+            player::PlayerEvent::Looped {
+                instance_id,
+                coll_id,
+                clip_id,
+            } => PlayerEvent::Looped {
+                instance_id: instance_id.to_string(),
+                coll_id: coll_id.to_string(),
+                clip_id: clip_id.to_string(),
+            },
+            player::PlayerEvent::GainChanged { coll_id, gain } => PlayerEvent::GainChanged {
+                coll_id: coll_id.to_string(),
+                gain,
+            },
+            player::PlayerEvent::IntensityChanged {
+                coll_id,
+                clip_id,
+                intensity,
+            } => PlayerEvent::IntensityChanged {
+                coll_id: coll_id.to_string(),
+                clip_id: clip_id.to_string(),
+                intensity,
+            },
+            player::PlayerEvent::Error {
+                coll_id,
+                clip_id,
+                message,
+            } => PlayerEvent::Error {
+                coll_id: coll_id.to_string(),
+                clip_id: clip_id.to_string(),
+                message,
+            },
+            player::PlayerEvent::LibraryChanged => PlayerEvent::LibraryChanged,
+            player::PlayerEvent::Scheduled { id, at, action } => {
+                PlayerEvent::Scheduled(Scheduled::new(id, at, action))
+            }
+            player::PlayerEvent::Fired { id, error } => PlayerEvent::Fired {
+                id: id.to_string(),
+                error,
+            },
+            player::PlayerEvent::Cancelled { id } => PlayerEvent::Cancelled { id: id.to_string() },
+            player::PlayerEvent::EmitterStarted { id, coll_id } => PlayerEvent::EmitterStarted {
+                id: id.to_string(),
+                coll_id: coll_id.to_string(),
+            },
+            player::PlayerEvent::Emitted {
+                id,
+                coll_id,
+                clip_id,
+            } => PlayerEvent::Emitted {
+                id: id.to_string(),
+                coll_id: coll_id.to_string(),
+                clip_id: clip_id.to_string(),
+            },
+            player::PlayerEvent::EmitterStopped { id } => {
+                PlayerEvent::EmitterStopped { id: id.to_string() }
+            }
         }
     }
 }
 
-#[derive(Deserialize)]
+/// `gain` goes from 0 to 10.
+#[derive(Deserialize, ToSchema)]
 pub struct PlaybackParams {
     pub gain: f64,
 }
+
+#[derive(Deserialize, ToSchema)]
+pub struct IntensityParams {
+    pub intensity: f64,
+}
+
+/// Query parameters for playing a clip. `pan` goes from -1 (left) to 1
+/// (right); `x` and `y` place the clip relative to the listener
+/// instead, which also makes distant clips quieter. Anything else is a
+/// bad request.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PlayQuery {
+    pub quantize: Option<player::Quantize>,
+    pub pan: Option<f64>,
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+}
+
+impl PlayQuery {
+    pub fn placement(&self) -> Option<model::Placement> {
+        match (self.pan, self.x, self.y) {
+            (Some(pan), _, _) => Some(model::Placement::Pan(pan)),
+            (None, Some(x), y) => Some(model::Placement::Position(model::Position {
+                x,
+                y: y.unwrap_or(0.0),
+            })),
+            (None, None, Some(y)) => {
+                Some(model::Placement::Position(model::Position { x: 0.0, y }))
+            }
+            (None, None, None) => None,
+        }
+    }
+}
+
+/// The body of every error response.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Error {
+    /// Stable and machine readable, e.g. "not_found".
+    #[schema(value_type = String)]
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+/// The body of a successful response which has nothing else to say.
+#[derive(Serialize, ToSchema)]
+pub struct Status {
+    #[schema(value_type = String)]
+    pub status: &'static str,
+}
+
+impl Status {
+    pub fn new(status: &'static str) -> Status {
+        Status { status }
+    }
+}
+
+/// Who the client is logged in as.
+#[derive(Serialize, ToSchema)]
+pub struct Session {
+    pub role: Role,
+}
+
+#[derive(Serialize, ToSchema)]
+pub enum Role {
+    Gm,
+    Player,
+}
+
+impl From<auth::Role> for Role {
+    fn from(m: auth::Role) -> Self {
+        match m {
+            auth::Role::Gm => Role::Gm,
+            auth::Role::Player => Role::Player,
+        }
+    }
+}
+
+/// A clip instance, as started by a play request.
+#[derive(Serialize, ToSchema)]
+pub struct Instance {
+    pub instance_id: String,
+    pub coll_id: String,
+    pub clip_id: String,
+    pub duration: f64,
+}
+
+impl From<player::Instance> for Instance {
+    fn from(m: player::Instance) -> Self {
+        Instance {
+            instance_id: m.id.to_string(),
+            coll_id: m.coll_id.to_string(),
+            clip_id: m.clip_id.to_string(),
+            duration: m.duration.as_secs_f64(),
+        }
+    }
+}
+
+/// Everything the player is doing, as of event number `seq`. A client
+/// catching up applies events numbered after that.
+#[derive(Serialize, ToSchema)]
+pub struct State {
+    pub seq: u64,
+    pub instances: Vec<InstanceState>,
+    /// Collections whose gain has been changed from 1
+    pub collections: Vec<CollectionState>,
+    pub buses: Vec<BusState>,
+    pub outputs: Vec<Output>,
+}
+
+impl State {
+    pub fn new(seq: u64, snapshot: player::Snapshot, outputs: Vec<output::OutputStatus>) -> Self {
+        State {
+            seq,
+            instances: snapshot.instances.into_iter().map(|i| i.into()).collect(),
+            collections: snapshot
+                .coll_gains
+                .into_iter()
+                .map(|(coll_id, gain)| CollectionState {
+                    coll_id: coll_id.to_string(),
+                    gain,
+                })
+                .collect(),
+            buses: snapshot
+                .buses
+                .into_iter()
+                .map(|(bus_id, effects)| BusState::new(bus_id, effects))
+                .collect(),
+            outputs: outputs.into_iter().map(|o| o.into()).collect(),
+        }
+    }
+}
+
+/// A playing clip instance. `time` is in seconds into the clip; a clip
+/// is `paused` while something of a higher priority plays. It has
+/// either a `pan` or a `position`.
+#[derive(Serialize, ToSchema)]
+pub struct InstanceState {
+    pub instance_id: String,
+    pub coll_id: String,
+    pub clip_id: String,
+    pub kind: model::CollectionKind,
+    pub paused: bool,
+    pub time: f64,
+    #[serde(rename = "loop")]
+    pub looping: bool,
+    pub gain: f64,
+    pub intensity: f64,
+    pub pan: Option<f64>,
+    pub position: Option<model::Position>,
+    pub playback_rate: f64,
+}
+
+impl From<player::InstanceState> for InstanceState {
+    fn from(m: player::InstanceState) -> Self {
+        let (pan, position) = match m.placement {
+            model::Placement::Pan(pan) => (Some(pan), None),
+            model::Placement::Position(position) => (None, Some(position)),
+        };
+        InstanceState {
+            instance_id: m.id.to_string(),
+            coll_id: m.coll_id.to_string(),
+            clip_id: m.clip_id.to_string(),
+            kind: m.kind,
+            paused: m.paused,
+            time: m.position,
+            looping: m.looping,
+            gain: m.gain,
+            intensity: m.intensity,
+            pan,
+            position,
+            playback_rate: m.playback_rate,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CollectionState {
+    pub coll_id: String,
+    pub gain: f64,
+}
+
+/// A bus and its effects' current settings. Either `kind` or `coll_id`
+/// says which bus it is.
+#[derive(Serialize, ToSchema)]
+pub struct BusState {
+    pub kind: Option<model::CollectionKind>,
+    pub coll_id: Option<String>,
+    #[schema(value_type = Vec<Object>)]
+    pub effects: Vec<model::Effect>,
+}
+
+impl BusState {
+    fn new(bus_id: player::BusId, effects: Vec<model::Effect>) -> Self {
+        let (kind, coll_id) = match bus_id {
+            player::BusId::Kind(kind) => (Some(kind), None),
+            player::BusId::Collection(coll_id) => (None, Some(coll_id.to_string())),
+        };
+        BusState {
+            kind,
+            coll_id,
+            effects,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Output {
+    pub name: String,
+    pub gain: f32,
+    /// How many times a buffered output has run dry, and how many times
+    /// it's had to drop audio. Absent for outputs which don't buffer.
+    pub underruns: Option<u64>,
+    pub overruns: Option<u64>,
+}
+
+impl From<output::OutputStatus> for Output {
+    fn from(status: output::OutputStatus) -> Self {
+        Output {
+            name: status.name,
+            gain: status.gain,
+            underruns: status.stats.as_ref().map(|stats| stats.underruns()),
+            overruns: status.stats.as_ref().map(|stats| stats.overruns()),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Recording {
+    /// The file being recorded to, if recording.
+    pub path: Option<String>,
+    /// Frames of the mix missed since the recording started, because
+    /// writing it out couldn't keep up.
+    pub dropped_frames: u64,
+}
+
+impl Recording {
+    pub fn new(path: Option<std::path::PathBuf>, dropped_frames: u64) -> Recording {
+        Recording {
+            path: path.map(|path| path.display().to_string()),
+            dropped_frames,
+        }
+    }
+}
+
+/// A request over the websocket. `id` is whatever the client likes, and
+/// comes back in the reply.
+#[derive(Deserialize)]
+pub struct WsRequest {
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub command: WsCommand,
+}
+
+/// The websocket's commands, which do the same as their http
+/// counterparts.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command")]
+pub enum WsCommand {
+    Play {
+        coll_id: String,
+        clip_id: String,
+        #[serde(default)]
+        options: PlayParams,
+    },
+    Stop {
+        coll_id: String,
+        clip_id: String,
+        #[serde(default)]
+        fade: StopParams,
+    },
+    StopInstance {
+        instance_id: String,
+        #[serde(default)]
+        fade: StopParams,
+    },
+    StopCollection {
+        coll_id: String,
+        #[serde(default)]
+        fade: StopParams,
+    },
+    StopAll {
+        #[serde(default)]
+        fade: StopParams,
+    },
+    SetGain {
+        coll_id: String,
+        gain: f64,
+    },
+    SetIntensity {
+        coll_id: String,
+        clip_id: String,
+        intensity: f64,
+    },
+    SetOutputGain {
+        name: String,
+        gain: f64,
+    },
+    Playing,
+}
+
+/// What the server sends over the websocket: replies to requests, and
+/// player events as they happen.
+#[derive(Serialize)]
+#[serde(tag = "type")]
+pub enum WsMessage {
+    Reply {
+        id: Option<String>,
+        #[serde(flatten)]
+        result: WsResult,
+    },
+    Event {
+        seq: u64,
+        event: PlayerEvent,
+    },
+    /// Sent instead of events the client was too slow to take; events
+    /// pick up again after `state.seq`.
+    Resync {
+        state: State,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WsResult {
+    /// What the http endpoint would have answered
+    Ok(serde_json::Value),
+    Error(Error),
+}
+
+/// An optional body for playing a clip. Anything given here overrides
+/// the query parameters and the collection kind's usual behavior.
+/// Durations and offsets are in seconds.
+#[derive(Deserialize, Default, Debug, ToSchema)]
+#[serde(default)]
+pub struct PlayParams {
+    pub gain: Option<f64>,
+    pub fade_in: Option<f64>,
+    pub start_offset: Option<f64>,
+    #[serde(rename = "loop")]
+    pub looping: Option<bool>,
+    pub playback_rate: Option<f64>,
+    pub pan: Option<f64>,
+    pub no_interrupt: bool,
+    pub quantize: Option<player::Quantize>,
+}
+
+impl PlayParams {
+    /// Apply these on top of `options`. None if any of them are out of
+    /// range.
+    pub fn into_options(self, options: player::PlayOptions) -> Option<player::PlayOptions> {
+        if !self.gain.map_or(true, |g| GAINS.contains(&g))
+            || !self
+                .start_offset
+                .map_or(true, |x| Duration::try_from_secs_f64(x).is_ok())
+            || !self
+                .playback_rate
+                .map_or(true, |r| PLAYBACK_RATES.contains(&r))
+            || !self
+                .pan
+                .map_or(true, |pan| model::Placement::Pan(pan).is_valid())
+        {
+            return None;
+        }
+        let fade_in = match self.fade_in {
+            Some(fade_in) => Some(Duration::try_from_secs_f64(fade_in).ok()?),
+            None => options.fade_in,
+        };
+
+        Some(player::PlayOptions {
+            quantize: self.quantize.or(options.quantize),
+            gain: self.gain.or(options.gain),
+            placement: self.pan.map(model::Placement::Pan).or(options.placement),
+            fade_in,
+            start_offset: self.start_offset.or(options.start_offset),
+            looping: self.looping.or(options.looping),
+            playback_rate: self.playback_rate.or(options.playback_rate),
+            no_interrupt: self.no_interrupt || options.no_interrupt,
+        })
+    }
+}
+
+/// How much faster or slower a clip may be played.
+const PLAYBACK_RATES: RangeInclusive<f64> = 0.01..=100.0;
+
+/// How loud a clip, collection or output may be made: the same range
+/// as an output's gain.
+pub const GAINS: RangeInclusive<f64> = 0.0..=output::MAX_OUTPUT_GAIN as f64;
+
+/// An optional body for the stop endpoints. `fade` is in seconds; 0
+/// cuts the sound off dead.
+#[derive(Deserialize, Default, Debug, ToSchema)]
+#[serde(default)]
+pub struct StopParams {
+    pub fade: Option<f64>,
+    pub curve: player::FadeCurve,
+}
+
+impl StopParams {
+    pub fn into_options(self) -> Option<player::StopOptions> {
+        let fade = match self.fade {
+            Some(fade) => Some(Duration::try_from_secs_f64(fade).ok()?),
+            None => None,
+        };
+
+        Some(player::StopOptions {
+            fade,
+            curve: self.curve,
+        })
+    }
+}
+
+/// Live settings for a playing clip. Give either `pan`, from -1 to 1,
+/// or `position`.
+#[derive(Deserialize, ToSchema)]
+pub struct ClipPlaybackParams {
+    pub pan: Option<f64>,
+    pub position: Option<model::Position>,
+}
+
+impl ClipPlaybackParams {
+    pub fn placement(&self) -> Option<model::Placement> {
+        match (self.pan, self.position) {
+            (Some(pan), _) => Some(model::Placement::Pan(pan)),
+            (None, Some(position)) => Some(model::Placement::Position(position)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// A live change to one effect on a bus. Parameters which don't apply
+/// to the effect are ignored. `mix`, `resonance` and `damping` go from
+/// 0 to 1, `feedback` from 0 up to but not including 1; `cutoff` is in
+/// Hz and `delay_time` in seconds, both above 0. `duration` is in
+/// seconds.
+#[derive(Deserialize, ToSchema)]
+pub struct EffectParams {
+    pub mode: Option<model::FilterMode>,
+    pub cutoff: Option<f64>,
+    pub resonance: Option<f64>,
+    pub feedback: Option<f64>,
+    pub damping: Option<f64>,
+    pub delay_time: Option<f64>,
+    pub mix: Option<f64>,
+    #[serde(default)]
+    pub duration: f64,
+}
+
+impl EffectParams {
+    pub fn into_change(self) -> Option<effects::EffectChange> {
+        if !self.duration.is_finite() || self.duration < 0.0 {
+            return None;
+        }
+
+        let change = effects::EffectChange {
+            mode: self.mode,
+            cutoff: self.cutoff,
+            resonance: self.resonance,
+            feedback: self.feedback,
+            damping: self.damping,
+            delay_time: self.delay_time,
+            mix: self.mix,
+            duration: Duration::from_secs_f64(self.duration),
+        };
+        change.is_valid().then_some(change)
+    }
+}
+
+/// A player action to run later. `at` is in seconds since the unix
+/// epoch; `delay` is in seconds from now. Exactly one must be given.
+#[derive(Deserialize, ToSchema)]
+pub struct ScheduleParams {
+    pub action: ScheduledAction,
+    pub at: Option<f64>,
+    pub delay: Option<f64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Scheduled {
+    pub id: String,
+    pub at: f64,
+    pub action: ScheduledAction,
+}
+
+impl Scheduled {
+    pub fn new(id: u64, at: SystemTime, action: scheduler::Action) -> Self {
+        Scheduled {
+            id: id.to_string(),
+            at: at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            action: action.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub enum ScheduledAction {
+    Play {
+        coll_id: String,
+        clip_id: String,
+    },
+    Stop {
+        coll_id: String,
+        clip_id: String,
+    },
+    StopCollection {
+        coll_id: String,
+    },
+    StopAll,
+    Fade {
+        coll_id: String,
+        gain: f64,
+        /// In seconds
+        duration: f64,
+    },
+}
+
+impl From<scheduler::Action> for ScheduledAction {
+    fn from(m: scheduler::Action) -> Self {
+        match m {
+            scheduler::Action::Play { coll_id, clip_id } => ScheduledAction::Play {
+                coll_id: coll_id.to_string(),
+                clip_id: clip_id.to_string(),
+            },
+            scheduler::Action::Stop { coll_id, clip_id } => ScheduledAction::Stop {
+                coll_id: coll_id.to_string(),
+                clip_id: clip_id.to_string(),
+            },
+            scheduler::Action::StopCollection { coll_id } => ScheduledAction::StopCollection {
+                coll_id: coll_id.to_string(),
+            },
+            scheduler::Action::StopAll => ScheduledAction::StopAll,
+            scheduler::Action::Fade {
+                coll_id,
+                gain,
+                duration,
+            } => ScheduledAction::Fade {
+                coll_id: coll_id.to_string(),
+                gain,
+                duration: duration.as_secs_f64(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum InvalidAction {
+    #[error("Bad id: {0}")]
+    Id(#[from] ParseIntError),
+    #[error("Bad duration: {0}")]
+    Duration(#[from] TryFromFloatSecsError),
+    #[error("Gain out of range: {0}")]
+    Gain(f64),
+}
+
+impl TryFrom<ScheduledAction> for scheduler::Action {
+    type Error = InvalidAction;
+
+    fn try_from(a: ScheduledAction) -> Result<Self, Self::Error> {
+        Ok(match a {
+            ScheduledAction::Play { coll_id, clip_id } => scheduler::Action::Play {
+                coll_id: coll_id.parse()?,
+                clip_id: clip_id.parse()?,
+            },
+            ScheduledAction::Stop { coll_id, clip_id } => scheduler::Action::Stop {
+                coll_id: coll_id.parse()?,
+                clip_id: clip_id.parse()?,
+            },
+            ScheduledAction::StopCollection { coll_id } => scheduler::Action::StopCollection {
+                coll_id: coll_id.parse()?,
+            },
+            ScheduledAction::StopAll => scheduler::Action::StopAll,
+            ScheduledAction::Fade {
+                coll_id,
+                gain,
+                duration,
+            } => {
+                if !GAINS.contains(&gain) {
+                    return Err(InvalidAction::Gain(gain));
+                }
+                scheduler::Action::Fade {
+                    coll_id: coll_id.parse()?,
+                    gain,
+                    duration: Duration::try_from_secs_f64(duration)?,
+                }
+            }
+        })
+    }
+}
+
+/// Settings for a random emitter. Intervals are in seconds, from 0.1
+/// up to a day; gains go from 0 to 10, and pan from -1 (left) to 1
+/// (right). Each firing picks a value uniformly between the min and
+/// the max, and plays its clip once.
+#[derive(Deserialize, ToSchema)]
+pub struct EmitterParams {
+    pub tag: Option<String>,
+    pub min_interval: f64,
+    pub max_interval: f64,
+    #[serde(default = "one")]
+    pub min_gain: f64,
+    #[serde(default = "one")]
+    pub max_gain: f64,
+    #[serde(default)]
+    pub min_pan: f64,
+    #[serde(default)]
+    pub max_pan: f64,
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+impl EmitterParams {
+    pub fn into_settings(self, coll_id: u64) -> Option<emitter::EmitterSettings> {
+        let valid = |min: f64, max: f64| min.is_finite() && max.is_finite() && min <= max;
+        if !(valid(self.min_interval, self.max_interval)
+            && valid(self.min_gain, self.max_gain)
+            && GAINS.contains(&self.min_gain)
+            && GAINS.contains(&self.max_gain)
+            && valid(self.min_pan, self.max_pan)
+            && self.min_interval >= emitter::MIN_INTERVAL
+            && self.max_interval <= emitter::MAX_INTERVAL)
+        {
+            return None;
+        }
+
+        Some(emitter::EmitterSettings {
+            coll_id,
+            tag: self.tag,
+            interval: self.min_interval..=self.max_interval,
+            gain: self.min_gain..=self.max_gain,
+            pan: self.min_pan..=self.max_pan,
+        })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Emitter {
+    pub id: String,
+    pub coll_id: String,
+    pub tag: Option<String>,
+    pub min_interval: f64,
+    pub max_interval: f64,
+    pub min_gain: f64,
+    pub max_gain: f64,
+    pub min_pan: f64,
+    pub max_pan: f64,
+}
+
+impl Emitter {
+    pub fn new(id: u64, settings: emitter::EmitterSettings) -> Self {
+        Emitter {
+            id: id.to_string(),
+            coll_id: settings.coll_id.to_string(),
+            tag: settings.tag,
+            min_interval: *settings.interval.start(),
+            max_interval: *settings.interval.end(),
+            min_gain: *settings.gain.start(),
+            max_gain: *settings.gain.end(),
+            min_pan: *settings.pan.start(),
+            max_pan: *settings.pan.end(),
+        }
+    }
+}
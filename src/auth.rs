@@ -0,0 +1,475 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Form},
+    http::{header, HeaderMap, Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{player::PlayerEvent, server::ApiError};
+
+/// The name of the cookie holding a logged in browser's session.
+const SESSION_COOKIE: &str = "soundboard_session";
+
+/// How long a login lasts: long enough for a session of the game.
+const SESSION_TTL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// After this many wrong passwords in a row, an address has to wait
+/// `LOCKOUT` before trying again.
+const MAX_FAILURES: u32 = 5;
+const LOCKOUT: Duration = Duration::from_secs(60);
+
+/// How long a wrong password takes to be turned away, to slow down
+/// guessing.
+const FAILURE_DELAY: Duration = Duration::from_secs(1);
+
+/// Who may do what, when the board has passwords. Without them,
+/// everybody is a GM.
+pub struct Auth {
+    gm_password: String,
+    player_password: Option<String>,
+    player_collections: Arc<HashSet<u64>>,
+    // Served over https, so cookies should only go back that way
+    secure: bool,
+    // Logged in browsers, by session token
+    sessions: Mutex<HashMap<String, Session>>,
+    // Wrong passwords in a row, and when the last one was, by address
+    failures: Mutex<HashMap<IpAddr, (u32, Instant)>>,
+}
+
+struct Session {
+    role: Role,
+    expires: Instant,
+}
+
+/// Why a client isn't logged in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rejected {
+    NoPassword,
+    WrongPassword,
+    /// Too many wrong passwords; nothing is checked for a while.
+    LockedOut,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Can do everything.
+    Gm,
+    /// Can play and stop clips from a few collections.
+    Player,
+}
+
+/// What the client making a request is allowed to do.
+#[derive(Clone, Debug)]
+pub enum Access {
+    Gm,
+    /// Only these collections
+    Player(Arc<HashSet<u64>>),
+}
+
+impl Access {
+    pub fn role(&self) -> Role {
+        match self {
+            Access::Gm => Role::Gm,
+            Access::Player(_) => Role::Player,
+        }
+    }
+
+    /// May this client start and stop clips in a collection?
+    pub fn may_control(&self, coll_id: u64) -> bool {
+        match self {
+            Access::Gm => true,
+            Access::Player(collections) => collections.contains(&coll_id),
+        }
+    }
+
+    /// May this client hear about an event? Players only hear about
+    /// their own collections.
+    pub fn may_see(&self, event: &PlayerEvent) -> bool {
+        match event {
+            PlayerEvent::Started { coll_id, .. }
+            | PlayerEvent::Stopped { coll_id, .. }
+            | PlayerEvent::Paused { coll_id, .. }
+            | PlayerEvent::Resumed { coll_id, .. }
+            | PlayerEvent::Looped { coll_id, .. }
+            | PlayerEvent::GainChanged { coll_id, .. }
+            | PlayerEvent::IntensityChanged { coll_id, .. }
+            | PlayerEvent::Error { coll_id, .. } => self.may_control(*coll_id),
+            PlayerEvent::LibraryChanged => true,
+            // Scheduling and emitters are the GM's business.
+            _ => matches!(self, Access::Gm),
+        }
+    }
+
+    // `path` is without the /api/v1 prefix, if it had one.
+    fn allows(&self, method: &Method, path: &str) -> bool {
+        if let Access::Gm = self {
+            return true;
+        }
+        if is_public(path) {
+            return true;
+        }
+
+        let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+        let coll_id = match (method.as_str(), segments.as_slice()) {
+            // Each of these only shows players their own collections.
+            // The websocket checks each command itself.
+            (
+                "GET",
+                [""]
+                | ["index.js" | "collection" | "state" | "playing" | "events" | "ws" | "session"],
+            ) => return true,
+            ("GET", ["collection", coll_id, "clip", _, "audio"]) => coll_id,
+            ("POST", ["collection", coll_id, "clip", _, "play" | "stop"]) => coll_id,
+            ("POST", ["collection", coll_id, "stop"]) => coll_id,
+            _ => return false,
+        };
+        coll_id
+            .parse()
+            .map(|coll_id| self.may_control(coll_id))
+            .unwrap_or(false)
+    }
+}
+
+impl Auth {
+    pub fn new(
+        gm_password: String,
+        player_password: Option<String>,
+        player_collections: HashSet<u64>,
+        secure: bool,
+    ) -> Auth {
+        Auth {
+            gm_password,
+            player_password,
+            player_collections: Arc::new(player_collections),
+            secure,
+            sessions: Default::default(),
+            failures: Default::default(),
+        }
+    }
+
+    fn access(&self, role: Role) -> Access {
+        match role {
+            Role::Gm => Access::Gm,
+            Role::Player => Access::Player(self.player_collections.clone()),
+        }
+    }
+
+    fn check_password(&self, password: &str) -> Option<Role> {
+        if constant_time_eq(password, &self.gm_password) {
+            return Some(Role::Gm);
+        }
+        match &self.player_password {
+            Some(player_password) if constant_time_eq(password, player_password) => {
+                Some(Role::Player)
+            }
+            _ => None,
+        }
+    }
+
+    // Check a password from `ip`, unless it's been guessing.
+    fn attempt(&self, ip: IpAddr, password: &str) -> Result<Role, Rejected> {
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, (_, last)| last.elapsed() < LOCKOUT);
+        if let Some((count, _)) = failures.get(&ip) {
+            if *count >= MAX_FAILURES {
+                return Err(Rejected::LockedOut);
+            }
+        }
+
+        match self.check_password(password) {
+            Some(role) => {
+                failures.remove(&ip);
+                Ok(role)
+            }
+            None => {
+                let (count, last) = failures.entry(ip).or_insert((0, Instant::now()));
+                *count += 1;
+                *last = Instant::now();
+                if *count >= MAX_FAILURES {
+                    info!("Too many wrong passwords from {ip}; locking it out");
+                }
+                Err(Rejected::WrongPassword)
+            }
+        }
+    }
+
+    /// Start a session, if the password is right. Returns its token.
+    fn login(&self, ip: IpAddr, password: &str) -> Result<String, Rejected> {
+        let role = self.attempt(ip, password)?;
+        let token = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect::<String>();
+
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.expires > now);
+        sessions.insert(
+            token.clone(),
+            Session {
+                role,
+                expires: now + SESSION_TTL,
+            },
+        );
+        info!("Logged in as {role:?}");
+        Ok(token)
+    }
+
+    fn logout(&self, token: &str) {
+        self.sessions.lock().unwrap().remove(token);
+    }
+
+    // A Set-Cookie header for the session cookie.
+    fn session_cookie(&self, value: &str, attributes: &str) -> (header::HeaderName, String) {
+        let secure = if self.secure { "; Secure" } else { "" };
+        (
+            header::SET_COOKIE,
+            format!(
+                "{SESSION_COOKIE}={value}; Path=/; HttpOnly; SameSite=Strict{secure}{attributes}"
+            ),
+        )
+    }
+
+    // A browser has a session cookie; anything else can send a password
+    // as a bearer token.
+    fn role_for<B>(&self, request: &Request<B>) -> Result<Role, Rejected> {
+        if let Some(token) = session_token(request.headers()) {
+            let mut sessions = self.sessions.lock().unwrap();
+            let session = sessions
+                .get(token)
+                .map(|session| (session.role, session.expires));
+            match session {
+                Some((role, expires)) if expires > Instant::now() => return Ok(role),
+                Some(_) => {
+                    sessions.remove(token);
+                }
+                None => (),
+            }
+        }
+
+        let bearer = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match bearer {
+            Some(password) => self.attempt(client_ip(request.extensions()), password),
+            None => Err(Rejected::NoPassword),
+        }
+    }
+}
+
+fn session_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .find_map(|cookie| {
+            let (name, value) = cookie.trim().split_once('=')?;
+            (name == SESSION_COOKIE).then_some(value)
+        })
+}
+
+fn client_ip(extensions: &axum::http::Extensions) -> IpAddr {
+    // SAFETY: the server is always run with the client's address
+    extensions.get::<ConnectInfo<SocketAddr>>().unwrap().0.ip()
+}
+
+// So how long a comparison takes says nothing about the password.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+// Reachable without logging in: the login page itself, and what it needs.
+fn is_public(path: &str) -> bool {
+    path == "/login" || path == "/logout" || path.starts_with("/preact/")
+}
+
+/// Turn away requests which aren't allowed, and tell the handlers of
+/// the rest who's asking, as an `Access`.
+pub async fn require_login<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    // SAFETY: the server always has this extension
+    let auth = request
+        .extensions()
+        .get::<Option<Arc<Auth>>>()
+        .unwrap()
+        .clone();
+
+    let access = match auth {
+        None => Access::Gm,
+        Some(auth) => match auth.role_for(&request) {
+            Ok(role) => auth.access(role),
+            Err(_) if is_public(request.uri().path()) => return next.run(request).await,
+            Err(Rejected::WrongPassword) => {
+                tokio::time::sleep(FAILURE_DELAY).await;
+                return ApiError::unauthorized("Wrong password").into_response();
+            }
+            Err(Rejected::LockedOut) => {
+                return ApiError::too_many_requests("Too many wrong passwords; try again later")
+                    .into_response()
+            }
+            Err(Rejected::NoPassword) if request.uri().path() == "/" => {
+                return Redirect::to("/login").into_response()
+            }
+            Err(Rejected::NoPassword) => {
+                return ApiError::unauthorized("Log in first").into_response()
+            }
+        },
+    };
+
+    let path = request.uri().path();
+    let path = path.strip_prefix("/api/v1").unwrap_or(path);
+    if !access.allows(request.method(), path) {
+        return ApiError::forbidden("Not allowed for players").into_response();
+    }
+
+    request.extensions_mut().insert(access);
+    next.run(request).await
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    password: String,
+}
+
+pub async fn login(
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Extension(auth): Extension<Option<Arc<Auth>>>,
+    Form(form): Form<LoginForm>,
+) -> Response {
+    let auth = match auth {
+        Some(auth) => auth,
+        None => return Redirect::to("/").into_response(),
+    };
+
+    match auth.login(address.ip(), &form.password) {
+        Ok(token) => {
+            let max_age = format!("; Max-Age={}", SESSION_TTL.as_secs());
+            ([auth.session_cookie(&token, &max_age)], Redirect::to("/")).into_response()
+        }
+        Err(Rejected::LockedOut) => Redirect::to("/login?locked").into_response(),
+        Err(_) => {
+            tokio::time::sleep(FAILURE_DELAY).await;
+            Redirect::to("/login?failed").into_response()
+        }
+    }
+}
+
+pub async fn logout(headers: HeaderMap, Extension(auth): Extension<Option<Arc<Auth>>>) -> Response {
+    let auth = match auth {
+        Some(auth) => auth,
+        None => return Redirect::to("/").into_response(),
+    };
+    if let Some(token) = session_token(&headers) {
+        auth.logout(token);
+    }
+
+    (
+        [auth.session_cookie("", "; Max-Age=0")],
+        Redirect::to("/login"),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player() -> Access {
+        Access::Player(Arc::new(HashSet::from([1])))
+    }
+
+    #[test]
+    fn players_only_look_at_what_is_filtered_for_them() {
+        let access = player();
+        for path in [
+            "/",
+            "/collection",
+            "/state",
+            "/playing",
+            "/events",
+            "/ws",
+            "/session",
+        ] {
+            assert!(access.allows(&Method::GET, path), "{path}");
+        }
+        assert!(access.allows(&Method::GET, "/collection/1/clip/7/audio"));
+        assert!(!access.allows(&Method::GET, "/collection/2/clip/7/audio"));
+
+        for path in [
+            "/schedule",
+            "/emitter",
+            "/recording",
+            "/output",
+            "/stream.mp3",
+            "/openapi.json",
+        ] {
+            assert!(!access.allows(&Method::GET, path), "{path}");
+            assert!(Access::Gm.allows(&Method::GET, path), "{path}");
+        }
+    }
+
+    #[test]
+    fn players_only_control_their_own_collections() {
+        let access = player();
+        assert!(access.allows(&Method::POST, "/collection/1/clip/7/play"));
+        assert!(access.allows(&Method::POST, "/collection/1/clip/7/stop"));
+        assert!(access.allows(&Method::POST, "/collection/1/stop"));
+
+        assert!(!access.allows(&Method::POST, "/collection/2/clip/7/play"));
+        assert!(!access.allows(&Method::POST, "/collection/2/stop"));
+        assert!(!access.allows(&Method::POST, "/collection/x/stop"));
+        assert!(!access.allows(&Method::POST, "/collection/1/playback"));
+        assert!(!access.allows(&Method::POST, "/stop_all"));
+        assert!(!access.allows(&Method::DELETE, "/collection/1/stop"));
+
+        assert!(Access::Gm.allows(&Method::POST, "/stop_all"));
+    }
+
+    #[test]
+    fn locks_out_after_too_many_wrong_passwords() {
+        let auth = Auth::new("gm".into(), Some("player".into()), HashSet::new(), false);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+
+        assert_eq!(auth.attempt(ip, "player"), Ok(Role::Player));
+        for _ in 0..MAX_FAILURES {
+            assert_eq!(auth.attempt(ip, "wrong"), Err(Rejected::WrongPassword));
+        }
+        // Even the right password is turned away now, but only from
+        // that address.
+        assert_eq!(auth.attempt(ip, "gm"), Err(Rejected::LockedOut));
+        assert_eq!(auth.attempt(other, "gm"), Ok(Role::Gm));
+    }
+
+    #[test]
+    fn a_right_password_starts_the_count_over() {
+        let auth = Auth::new("gm".into(), None, HashSet::new(), false);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        for _ in 1..MAX_FAILURES {
+            assert_eq!(auth.attempt(ip, "wrong"), Err(Rejected::WrongPassword));
+        }
+        assert_eq!(auth.attempt(ip, "gm"), Ok(Role::Gm));
+        assert_eq!(auth.attempt(ip, "wrong"), Err(Rejected::WrongPassword));
+        // Without a player password, nothing else gets in.
+        assert_eq!(auth.attempt(ip, ""), Err(Rejected::WrongPassword));
+    }
+}
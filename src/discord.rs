@@ -2,6 +2,7 @@ use std::{collections::HashMap, error::Error, sync::Arc};
 
 use futures::StreamExt;
 use tokio::sync::RwLock;
+use tracing::info;
 use twilight_model::{
     channel::{Channel, ChannelType},
     id::{
@@ -11,6 +12,8 @@ use twilight_model::{
     user::CurrentUserGuild,
 };
 
+use crate::output::RingBufferSource;
+
 #[derive(Debug)]
 pub struct DiscordConnection {
     http: twilight_http::Client,
@@ -20,9 +23,9 @@ pub struct DiscordConnection {
 }
 
 impl DiscordConnection {
-    /// Connect to a discord voice channel, and start relaying float pcm audio from audio_consumer.
+    /// Connect to a discord voice channel, and start relaying float pcm audio from audio_source.
     pub async fn connect(
-        audio_consumer: ringbuf::HeapConsumer<u8>,
+        audio_source: RingBufferSource,
         token: String,
     ) -> Result<Arc<Self>, Box<dyn Error + Send + Sync>> {
         let (mut events, conn) = {
@@ -61,7 +64,7 @@ impl DiscordConnection {
         // Join the first guild
         let guilds = conn.get_guilds().await?;
         let guild = guilds.get(0).expect("no guilds!");
-        println!("Joining guild '{}' (id={})", guild.name, guild.id);
+        info!("Joining guild '{}' (id={})", guild.name, guild.id);
 
         let channels = conn.get_channels(guild.id).await?;
         let voice_channels = channels
@@ -71,7 +74,7 @@ impl DiscordConnection {
 
         // Find the first voice channel
         let channel = voice_channels.get(0).expect("no voice channels!");
-        println!(
+        info!(
             "Joining channel '{}' (id={})",
             channel.name.as_deref().unwrap_or_default(),
             channel.id
@@ -83,7 +86,7 @@ impl DiscordConnection {
         conn.send_message(channel.id, "BLEEP BLOOP").await?;
 
         let reader = songbird::input::reader::Reader::Extension(Box::new(RingBufferMediaSource {
-            audio_consumer,
+            audio_source,
         }));
 
         let input = songbird::input::Input::float_pcm(true, reader);
@@ -143,7 +146,7 @@ impl DiscordConnection {
 }
 
 struct RingBufferMediaSource {
-    audio_consumer: ringbuf::HeapConsumer<u8>,
+    audio_source: RingBufferSource,
 }
 
 impl songbird::input::reader::MediaSource for RingBufferMediaSource {
@@ -158,7 +161,7 @@ impl songbird::input::reader::MediaSource for RingBufferMediaSource {
 
 impl std::io::Read for RingBufferMediaSource {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let read_bytes = self.audio_consumer.pop_slice(buf);
+        let read_bytes = self.audio_source.read(buf);
         if read_bytes == 0 {
             // HACK synthesize some silence to keep the audio channel running
             // TODO hit the play button only when we're actually playing
@@ -0,0 +1,205 @@
+use std::time::Duration;
+
+use kira::{
+    track::{
+        effect::{
+            delay::{DelayBuilder, DelayHandle},
+            filter::{self, FilterBuilder, FilterHandle},
+            reverb::{ReverbBuilder, ReverbHandle},
+        },
+        TrackBuilder,
+    },
+    tween::Tween,
+    CommandError,
+};
+
+use crate::model::{Effect, FilterMode, ReverbSettings};
+
+/// The effects every per-kind bus gets: a wide-open low-pass filter and
+/// a silent reverb, ready to be swept in.
+pub fn kind_bus_effects() -> Vec<Effect> {
+    vec![
+        Effect::Filter(Default::default()),
+        Effect::Reverb(ReverbSettings {
+            mix: 0.0,
+            ..Default::default()
+        }),
+    ]
+}
+
+pub enum EffectHandle {
+    Filter(FilterHandle),
+    Reverb(ReverbHandle),
+    Delay(DelayHandle),
+}
+
+/// A live change to an effect's parameters. Parameters which don't
+/// apply to the effect are ignored.
+#[derive(Default, Debug)]
+pub struct EffectChange {
+    pub mode: Option<FilterMode>,
+    pub cutoff: Option<f64>,
+    pub resonance: Option<f64>,
+    pub feedback: Option<f64>,
+    pub damping: Option<f64>,
+    pub delay_time: Option<f64>,
+    pub mix: Option<f64>,
+    /// How long to take getting to the new values.
+    pub duration: Duration,
+}
+
+// What the parameters may be set to. A feedback of 1 or more would
+// never die away.
+fn is_fraction(x: f64) -> bool {
+    (0.0..=1.0).contains(&x)
+}
+
+fn is_feedback(x: f64) -> bool {
+    (0.0..1.0).contains(&x)
+}
+
+fn is_positive(x: f64) -> bool {
+    x.is_finite() && x > 0.0
+}
+
+/// Are an effect's settings all ones it can take?
+pub fn is_valid(effect: &Effect) -> bool {
+    match effect {
+        Effect::Filter(s) => {
+            is_positive(s.cutoff) && is_fraction(s.resonance) && is_fraction(s.mix)
+        }
+        Effect::Reverb(s) => {
+            is_feedback(s.feedback) && is_fraction(s.damping) && is_fraction(s.mix)
+        }
+        Effect::Delay(s) => {
+            is_positive(s.delay_time) && is_feedback(s.feedback) && is_fraction(s.mix)
+        }
+    }
+}
+
+impl EffectChange {
+    /// Are the new values all ones the effects can take?
+    pub fn is_valid(&self) -> bool {
+        self.cutoff.map_or(true, is_positive)
+            && self.resonance.map_or(true, is_fraction)
+            && self.feedback.map_or(true, is_feedback)
+            && self.damping.map_or(true, is_fraction)
+            && self.delay_time.map_or(true, is_positive)
+            && self.mix.map_or(true, is_fraction)
+    }
+}
+
+/// Add `effects` to a track builder, in order.
+pub fn add_effects(builder: &mut TrackBuilder, effects: &[Effect]) -> Vec<EffectHandle> {
+    effects
+        .iter()
+        .map(|effect| match effect {
+            Effect::Filter(s) => EffectHandle::Filter(
+                builder.add_effect(
+                    FilterBuilder::new()
+                        .mode(kira_filter_mode(s.mode))
+                        .cutoff(s.cutoff)
+                        .resonance(s.resonance)
+                        .mix(s.mix),
+                ),
+            ),
+            Effect::Reverb(s) => EffectHandle::Reverb(
+                builder.add_effect(
+                    ReverbBuilder::new()
+                        .feedback(s.feedback)
+                        .damping(s.damping)
+                        .mix(s.mix),
+                ),
+            ),
+            Effect::Delay(s) => EffectHandle::Delay(
+                builder.add_effect(
+                    DelayBuilder::new()
+                        .delay_time(s.delay_time)
+                        .feedback(s.feedback)
+                        .mix(s.mix),
+                ),
+            ),
+        })
+        .collect()
+}
+
+impl EffectHandle {
+    pub fn apply(&mut self, change: &EffectChange) -> Result<(), CommandError> {
+        let tween = Tween {
+            duration: change.duration,
+            ..Default::default()
+        };
+
+        match self {
+            EffectHandle::Filter(h) => {
+                if let Some(mode) = change.mode {
+                    h.set_mode(kira_filter_mode(mode))?;
+                }
+                if let Some(cutoff) = change.cutoff {
+                    h.set_cutoff(cutoff, tween)?;
+                }
+                if let Some(resonance) = change.resonance {
+                    h.set_resonance(resonance, tween)?;
+                }
+                if let Some(mix) = change.mix {
+                    h.set_mix(mix, tween)?;
+                }
+            }
+            EffectHandle::Reverb(h) => {
+                if let Some(feedback) = change.feedback {
+                    h.set_feedback(feedback, tween)?;
+                }
+                if let Some(damping) = change.damping {
+                    h.set_damping(damping, tween)?;
+                }
+                if let Some(mix) = change.mix {
+                    h.set_mix(mix, tween)?;
+                }
+            }
+            EffectHandle::Delay(h) => {
+                if let Some(delay_time) = change.delay_time {
+                    h.set_delay_time(delay_time, tween)?;
+                }
+                if let Some(feedback) = change.feedback {
+                    h.set_feedback(feedback, tween)?;
+                }
+                if let Some(mix) = change.mix {
+                    h.set_mix(mix, tween)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Keep track of what an effect's been changed to.
+pub fn update_settings(effect: &mut Effect, change: &EffectChange) {
+    match effect {
+        Effect::Filter(s) => {
+            s.mode = change.mode.unwrap_or(s.mode);
+            s.cutoff = change.cutoff.unwrap_or(s.cutoff);
+            s.resonance = change.resonance.unwrap_or(s.resonance);
+            s.mix = change.mix.unwrap_or(s.mix);
+        }
+        Effect::Reverb(s) => {
+            s.feedback = change.feedback.unwrap_or(s.feedback);
+            s.damping = change.damping.unwrap_or(s.damping);
+            s.mix = change.mix.unwrap_or(s.mix);
+        }
+        Effect::Delay(s) => {
+            s.delay_time = change.delay_time.unwrap_or(s.delay_time);
+            s.feedback = change.feedback.unwrap_or(s.feedback);
+            s.mix = change.mix.unwrap_or(s.mix);
+        }
+    }
+}
+
+fn kira_filter_mode(mode: FilterMode) -> filter::FilterMode {
+    match mode {
+        FilterMode::LowPass => filter::FilterMode::LowPass,
+        FilterMode::BandPass => filter::FilterMode::BandPass,
+        FilterMode::HighPass => filter::FilterMode::HighPass,
+        FilterMode::Notch => filter::FilterMode::Notch,
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    collections::HashMap,
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rand::{seq::SliceRandom, Rng};
+use tokio::{
+    sync::{Mutex, RwLock},
+    task::JoinHandle,
+};
+use tracing::error;
+
+use crate::{
+    events::EventBus,
+    model::{Library, Placement},
+    player::{PlayOptions, Player, PlayerError, PlayerEvent},
+};
+
+/// The shortest time allowed between firings, in seconds. Anything
+/// quicker is just decoding files back to back.
+pub const MIN_INTERVAL: f64 = 0.1;
+/// The longest, a day.
+pub const MAX_INTERVAL: f64 = 24.0 * 60.0 * 60.0;
+
+/// Plays random clips from a collection at random intervals, for
+/// sprinkling one-shots over an ambience loop.
+pub struct Emitters {
+    library: Arc<RwLock<Library>>,
+    player: Arc<Mutex<Player>>,
+    events: EventBus,
+    running: Mutex<HashMap<u64, Emitter>>,
+    next_id: AtomicU64,
+}
+
+struct Emitter {
+    settings: EmitterSettings,
+    task: JoinHandle<()>,
+}
+
+#[derive(Clone, Debug)]
+pub struct EmitterSettings {
+    pub coll_id: u64,
+    /// Only pick clips with this tag.
+    pub tag: Option<String>,
+    /// Seconds between firings.
+    pub interval: RangeInclusive<f64>,
+    pub gain: RangeInclusive<f64>,
+    /// From -1 (left) to 1 (right).
+    pub pan: RangeInclusive<f64>,
+}
+
+impl Emitters {
+    pub fn new(
+        library: Arc<RwLock<Library>>,
+        player: Arc<Mutex<Player>>,
+        events: EventBus,
+    ) -> Emitters {
+        Emitters {
+            library,
+            player,
+            events,
+            running: Default::default(),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Start an emitter. Fails if the collection doesn't exist or has
+    /// no clips matching the tag.
+    pub async fn start(&self, settings: EmitterSettings) -> Result<u64, PlayerError> {
+        let library = self.library.read().await;
+        let coll = library
+            .collection(settings.coll_id)
+            .ok_or(PlayerError::NotFound)?;
+        let clip_ids = coll
+            .clips
+            .iter()
+            .filter(|clip| match &settings.tag {
+                Some(tag) => clip.tags.contains(tag),
+                None => true,
+            })
+            .map(|clip| clip.id)
+            .collect::<Vec<_>>();
+        drop(library);
+        if clip_ids.is_empty() {
+            return Err(PlayerError::NotFound);
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let task = {
+            let settings = settings.clone();
+            let library = self.library.clone();
+            let player = self.player.clone();
+            let events = self.events.clone();
+            tokio::spawn(async move {
+                loop {
+                    let delay = rand::thread_rng().gen_range(settings.interval.clone());
+                    tokio::time::sleep(Duration::from_secs_f64(delay)).await;
+
+                    let (clip_id, options) = {
+                        let mut rng = rand::thread_rng();
+                        // SAFETY: clip_ids was checked to be non-empty above
+                        let clip_id = *clip_ids.choose(&mut rng).unwrap();
+                        let options = PlayOptions {
+                            gain: Some(rng.gen_range(settings.gain.clone())),
+                            placement: Some(Placement::Pan(rng.gen_range(settings.pan.clone()))),
+                            // Each firing is a one-shot, even from a
+                            // collection that normally loops.
+                            looping: Some(false),
+                            ..Default::default()
+                        };
+                        (clip_id, options)
+                    };
+
+                    if let Err(e) =
+                        emit(&library, &player, settings.coll_id, clip_id, options).await
+                    {
+                        error!(err = &e as &dyn std::error::Error, "Error firing emitter");
+                        continue;
+                    }
+
+                    events.send(PlayerEvent::Emitted {
+                        id,
+                        coll_id: settings.coll_id,
+                        clip_id,
+                    });
+                }
+            })
+        };
+
+        self.running
+            .lock()
+            .await
+            .insert(id, Emitter { settings, task });
+        self.events.send(PlayerEvent::EmitterStarted {
+            id,
+            coll_id: settings.coll_id,
+        });
+
+        Ok(id)
+    }
+
+    /// Stop an emitter. Clips it has already started keep playing.
+    /// Returns false if there was no such emitter.
+    pub async fn stop(&self, id: u64) -> bool {
+        let emitter = self.running.lock().await.remove(&id);
+        match emitter {
+            Some(emitter) => {
+                emitter.task.abort();
+                self.events.send(PlayerEvent::EmitterStopped { id });
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub async fn running(&self) -> Vec<(u64, EmitterSettings)> {
+        let running = self.running.lock().await;
+        let mut emitters = running
+            .iter()
+            .map(|(id, emitter)| (*id, emitter.settings.clone()))
+            .collect::<Vec<_>>();
+        emitters.sort_by_key(|(id, _)| *id);
+        emitters
+    }
+}
+
+async fn emit(
+    library: &RwLock<Library>,
+    player_mutex: &Mutex<Player>,
+    coll_id: u64,
+    clip_id: u64,
+    options: PlayOptions,
+) -> Result<(), PlayerError> {
+    // The clip may have gone since the emitter started.
+    let library = library.read().await;
+    let coll = library.collection(coll_id).ok_or(PlayerError::NotFound)?;
+    let clip = coll.clip(clip_id).ok_or(PlayerError::NotFound)?;
+    let mut player = player_mutex.lock().await;
+    player.play_clip(coll, clip, options).map(|_| ())
+}
@@ -0,0 +1,233 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use futures::Stream;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::player::PlayerEvent;
+
+/// A player event, numbered in the order it happened.
+#[derive(Clone, Debug)]
+pub struct Sequenced {
+    pub seq: u64,
+    pub event: PlayerEvent,
+}
+
+/// Where every player event goes, for anyone who's listening. Events
+/// are numbered from 1, so a client can tell which ones a snapshot of
+/// the player already accounts for.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Sequenced>,
+    // Held while sending, so events go out in the order they're
+    // numbered, and a resuming listener sees each one exactly once.
+    sent: Arc<Mutex<Sent>>,
+}
+
+struct Sent {
+    // The last number handed out
+    last_seq: u64,
+    // The most recent events, oldest first
+    history: VecDeque<Sequenced>,
+    history_len: usize,
+}
+
+/// What a listener coming back missed while it was away.
+pub enum Missed {
+    Events(Vec<Sequenced>),
+    /// Some of what it missed is no longer kept, so it needs to start
+    /// over from a snapshot.
+    TooMany,
+}
+
+/// What a listener hears: the next event, or when it's fallen too far
+/// behind for that, a snapshot to start over from.
+pub enum Update<S> {
+    Event(Sequenced),
+    Resync(S),
+}
+
+impl EventBus {
+    /// `capacity` is how far a listener can fall behind before missing
+    /// events; `history_len` how many are kept for listeners which come
+    /// back.
+    pub fn new(capacity: usize, history_len: usize) -> EventBus {
+        let (sender, _) = broadcast::channel(capacity);
+        EventBus {
+            sender,
+            sent: Arc::new(Mutex::new(Sent {
+                last_seq: 0,
+                history: VecDeque::with_capacity(history_len + 1),
+                history_len,
+            })),
+        }
+    }
+
+    /// Send an event, returning its number. Nobody may be listening;
+    /// that's fine.
+    pub fn send(&self, event: PlayerEvent) -> u64 {
+        let mut sent = self.sent.lock().unwrap();
+        sent.last_seq += 1;
+        let sequenced = Sequenced {
+            seq: sent.last_seq,
+            event,
+        };
+
+        sent.history.push_back(sequenced.clone());
+        if sent.history.len() > sent.history_len {
+            sent.history.pop_front();
+        }
+        let _ = self.sender.send(sequenced);
+
+        sent.last_seq
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Sequenced> {
+        self.sender.subscribe()
+    }
+
+    /// Pick up after event `seq`: the events since then, and a receiver
+    /// for the ones after those.
+    pub fn resume(&self, seq: u64) -> (Missed, broadcast::Receiver<Sequenced>) {
+        let sent = self.sent.lock().unwrap();
+        let receiver = self.sender.subscribe();
+
+        // A number from the future means we've restarted since.
+        if seq > sent.last_seq {
+            return (Missed::TooMany, receiver);
+        }
+
+        let oldest = sent
+            .history
+            .front()
+            .map(|sequenced| sequenced.seq)
+            .unwrap_or(sent.last_seq + 1);
+        if seq + 1 < oldest {
+            return (Missed::TooMany, receiver);
+        }
+
+        let missed = sent
+            .history
+            .iter()
+            .filter(|sequenced| sequenced.seq > seq)
+            .cloned()
+            .collect();
+        (Missed::Events(missed), receiver)
+    }
+
+    /// Every event which `visible` lets through, from now on or from
+    /// after `resume_from`. A listener which falls too far behind, or
+    /// resumes from further back than is kept, gets a `Resync` with
+    /// `snapshot()` instead, then the events after the snapshot's
+    /// number. Ends if there's no snapshot to be had.
+    pub fn updates<S, F, Fut>(
+        &self,
+        resume_from: Option<u64>,
+        visible: impl Fn(&PlayerEvent) -> bool,
+        snapshot: F,
+    ) -> impl Stream<Item = Update<S>>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Option<(u64, S)>>,
+    {
+        let (missed, mut receiver) = match resume_from {
+            Some(seq) => self.resume(seq),
+            None => (Missed::Events(vec![]), self.subscribe()),
+        };
+
+        async_stream::stream! {
+            let mut resync = false;
+            match missed {
+                Missed::Events(missed) => {
+                    for sequenced in missed.into_iter().filter(|s| visible(&s.event)) {
+                        yield Update::Event(sequenced);
+                    }
+                }
+                Missed::TooMany => resync = true,
+            }
+
+            // Events the last snapshot already accounts for
+            let mut skip_through = 0;
+            loop {
+                if resync {
+                    resync = false;
+                    match snapshot().await {
+                        Some((seq, state)) => {
+                            skip_through = seq;
+                            yield Update::Resync(state);
+                        }
+                        None => break,
+                    }
+                }
+
+                match receiver.recv().await {
+                    Ok(sequenced) if sequenced.seq <= skip_through => continue,
+                    Ok(sequenced) if !visible(&sequenced.event) => continue,
+                    Ok(sequenced) => yield Update::Event(sequenced),
+                    // Too slow to keep up: start it over from a snapshot.
+                    Err(RecvError::Lagged(_)) => resync = true,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }
+    }
+
+    /// The number of the last event sent, or 0 if there hasn't been one.
+    pub fn last_seq(&self) -> u64 {
+        self.sent.lock().unwrap().last_seq
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Which events resuming after `seq` catches up on; None if it has
+    // to start over.
+    fn missed_after(bus: &EventBus, seq: u64) -> Option<Vec<u64>> {
+        match bus.resume(seq).0 {
+            Missed::Events(events) => Some(events.iter().map(|ev| ev.seq).collect()),
+            Missed::TooMany => None,
+        }
+    }
+
+    #[test]
+    fn resumes_from_the_history() {
+        let bus = EventBus::new(16, 256);
+        for _ in 0..300 {
+            bus.send(PlayerEvent::LibraryChanged);
+        }
+
+        // 45 to 300 are kept.
+        assert_eq!(missed_after(&bus, 290), Some((291..=300).collect()));
+        assert_eq!(missed_after(&bus, 44), Some((45..=300).collect()));
+        assert_eq!(missed_after(&bus, 300), Some(vec![]));
+
+        // The receiver carries on from there.
+        let (_, mut receiver) = bus.resume(300);
+        bus.send(PlayerEvent::LibraryChanged);
+        assert_eq!(receiver.try_recv().unwrap().seq, 301);
+    }
+
+    #[test]
+    fn starts_over_from_outside_the_history() {
+        let bus = EventBus::new(16, 256);
+        for _ in 0..300 {
+            bus.send(PlayerEvent::LibraryChanged);
+        }
+
+        // 44 has been forgotten.
+        assert_eq!(missed_after(&bus, 43), None);
+        assert_eq!(missed_after(&bus, 0), None);
+        // From before a restart
+        assert_eq!(missed_after(&bus, 301), None);
+
+        // With nothing forgotten yet, anything can be caught up on.
+        let bus = EventBus::new(16, 256);
+        bus.send(PlayerEvent::LibraryChanged);
+        assert_eq!(missed_after(&bus, 0), Some(vec![1]));
+    }
+}
@@ -0,0 +1,71 @@
+use axum::{
+    async_trait,
+    extract::{
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        FromRequest, RequestParts,
+    },
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use crate::server::ApiError;
+
+// axum's own extractors answer a bad request with plain text. These
+// wrap them, so that the api's errors all look the same.
+
+/// A JSON request body, or response.
+pub struct Json<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for Json<T>
+where
+    axum::Json<T>: FromRequest<B, Rejection = JsonRejection>,
+    B: Send,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let axum::Json(value) = axum::Json::from_request(req).await?;
+        Ok(Json(value))
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+/// Parameters from the request's path.
+pub struct Path<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for Path<T>
+where
+    axum::extract::Path<T>: FromRequest<B, Rejection = PathRejection>,
+    B: Send,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let axum::extract::Path(value) = axum::extract::Path::from_request(req).await?;
+        Ok(Path(value))
+    }
+}
+
+/// Parameters from the request's query string.
+pub struct Query<T>(pub T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for Query<T>
+where
+    axum::extract::Query<T>: FromRequest<B, Rejection = QueryRejection>,
+    B: Send,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let axum::extract::Query(value) = axum::extract::Query::from_request(req).await?;
+        Ok(Query(value))
+    }
+}
@@ -1,18 +1,31 @@
 #![allow(unused)]
 
 mod api;
+mod auth;
 mod discord;
+mod effects;
+mod emitter;
+mod events;
+mod extract;
 mod model;
+mod output;
+mod pcm;
 mod player;
+mod recorder;
+mod scheduler;
 mod server;
+mod stream;
+mod tls;
+mod ws;
 
 use clap::Parser;
 use hyper::body::Buf;
 use model::{Collection, Library};
-use player::{Player, PlayerEvent};
+use output::Outputs;
+use player::Player;
 use ringbuf::HeapRb;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::{Read, Seek},
     net::SocketAddr,
     path::PathBuf,
@@ -25,7 +38,10 @@ use tracing::error;
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
+    // Logs go to stderr, so stdout is free for pcm output.
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
     if let Err(e) = run().await {
         error!(err = ?e.as_ref());
     }
@@ -33,6 +49,43 @@ async fn main() {
 
 async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let args = Args::parse();
+
+    if args.list_devices {
+        for (name, is_default) in output::list_devices()? {
+            let default = if is_default { " (default)" } else { "" };
+            println!("{name}{default}");
+        }
+        return Ok(());
+    }
+
+    if args.discord_token.is_some()
+        && args
+            .sample_rate
+            .map_or(false, |rate| rate != output::DEFAULT_SAMPLE_RATE)
+    {
+        println!(
+            "Error: Discord needs a sample rate of {}.",
+            output::DEFAULT_SAMPLE_RATE
+        );
+        return Ok(());
+    }
+
+    let tls = match (args.tls_cert, args.tls_key) {
+        (Some(cert), Some(key)) => {
+            let files = tls::TlsFiles { cert, key };
+            if args.tls_self_signed {
+                let mut names = vec!["localhost".to_string()];
+                if !args.address.ip().is_unspecified() {
+                    names.push(args.address.ip().to_string());
+                }
+                names.extend(args.tls_name);
+                files.ensure_self_signed(names)?;
+            }
+            Some(files)
+        }
+        _ => None,
+    };
+
     let mut library = Library::default();
 
     for d in args.fx.into_iter() {
@@ -63,28 +116,115 @@ async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         return Ok(());
     }
 
-    let library = Arc::new(library);
+    // Without discord, play locally; with it, only if asked to.
+    let play_locally = !args.null_output && (args.discord_token.is_none() || args.monitor);
+    let cpal_settings = output::CpalSettings {
+        device: args.output_device,
+        buffer_size: args.buffer_size,
+    };
+    // Mix at whatever the sound card likes, unless something else
+    // needs a particular rate.
+    let sample_rate = match args.sample_rate {
+        Some(sample_rate) => sample_rate,
+        None if args.discord_token.is_none() && play_locally => {
+            output::device_sample_rate(&cpal_settings)?
+        }
+        None => output::DEFAULT_SAMPLE_RATE,
+    };
 
-    let (player, maybe_discord_conn) = match args.discord_token {
+    let recorder = Arc::new(recorder::Recorder::new(recorder::RecorderSettings {
+        directory: args.recording_dir,
+        rotate_after: args
+            .recording_rotate_minutes
+            .map(|minutes| Duration::from_secs(minutes * 60)),
+        sample_rate,
+    }));
+
+    let speed = if args.null_output {
+        args.null_speed
+    } else {
+        1.0
+    };
+    let mut outputs = Outputs::new(speed, sample_rate);
+    outputs.add("recording", recorder.tap(), args.recording_gain);
+
+    let maybe_discord_conn = match args.discord_token {
         Some(token) => {
-            let buffer_size = 1920 * 8;
-            let buffer = ringbuf::HeapRb::new(buffer_size);
-            let (audio_producer, audio_consumer) = buffer.split();
-            let player = Player::new(Some(audio_producer))?;
-            let conn = discord::DiscordConnection::connect(audio_consumer, token).await?;
-            (player, Some(conn))
+            let (sink, source) =
+                output::ring_buffer(sample_rate, Duration::from_millis(args.discord_latency_ms));
+            outputs.add("discord", sink, args.discord_gain);
+            Some(discord::DiscordConnection::connect(source, token).await?)
         }
-        None => (Player::new(None)?, None),
+        None => None,
     };
 
+    if let Some(target) = args.pcm_out {
+        outputs.add(
+            "pcm",
+            pcm::PcmSink::new(target, args.pcm_format)?,
+            args.pcm_gain,
+        );
+    }
+
+    let stream = if args.stream {
+        let (stream, sink) = stream::live_stream(sample_rate, args.stream_bitrate)?;
+        outputs.add("stream", sink, args.stream_gain);
+        Some(stream)
+    } else {
+        None
+    };
+
+    if play_locally {
+        outputs.add(
+            "local",
+            output::CpalSink::new(cpal_settings, sample_rate)?,
+            args.local_gain,
+        );
+    }
+
+    let output_controls = outputs.controls();
+    let mut player = Player::new(outputs)?;
+
+    for coll in library.collections.iter() {
+        player.add_collection_bus(coll)?;
+    }
+
+    let auth = match args.gm_password {
+        Some(gm_password) => {
+            let mut player_collections = HashSet::new();
+            for name in args.player_collection.iter() {
+                match library.collections.iter().find(|coll| &coll.name == name) {
+                    Some(coll) => player_collections.insert(coll.id),
+                    None => {
+                        println!("Error: There's no collection called {name}.");
+                        return Ok(());
+                    }
+                };
+            }
+            Some(Arc::new(auth::Auth::new(
+                gm_password,
+                args.player_password,
+                player_collections,
+                tls.is_some(),
+            )))
+        }
+        None if args.player_password.is_some() => {
+            println!("Error: A player password needs a GM password too.");
+            return Ok(());
+        }
+        None => None,
+    };
+
+    let library = Arc::new(RwLock::new(library));
+
     let player = Arc::new(Mutex::new(player));
-    let (player_event_tx, _) = tokio::sync::broadcast::channel::<PlayerEvent>(16);
+    let event_bus = events::EventBus::new(16, 256);
     let player_for_poller = player.clone();
-    let tx_for_poller = player_event_tx.clone();
+    let bus_for_poller = event_bus.clone();
     tokio::spawn(async move {
         loop {
             if let Err(e) =
-                player::poll_events(player_for_poller.clone(), tx_for_poller.clone()).await
+                player::poll_events(player_for_poller.clone(), bus_for_poller.clone()).await
             {
                 error!(err = &e as &dyn std::error::Error);
             }
@@ -93,7 +233,40 @@ async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         }
     });
 
-    server::run_server(args.address, library, player, player_event_tx).await
+    tokio::spawn(recorder::mark_events(
+        recorder.clone(),
+        library.clone(),
+        event_bus.subscribe(),
+    ));
+
+    let scheduler = Arc::new(scheduler::Scheduler::new(
+        library.clone(),
+        player.clone(),
+        event_bus.clone(),
+    ));
+
+    let emitters = Arc::new(emitter::Emitters::new(
+        library.clone(),
+        player.clone(),
+        event_bus.clone(),
+    ));
+
+    server::run_server(
+        args.address,
+        tls,
+        server::Services {
+            library,
+            player,
+            event_bus,
+            scheduler,
+            emitters,
+            recorder,
+            output_controls,
+            stream,
+            auth,
+        },
+    )
+    .await
 }
 
 #[derive(clap::Parser)]
@@ -122,8 +295,142 @@ struct Args {
     #[clap(long, value_parser, default_value = "127.0.0.1:14181")]
     address: SocketAddr,
 
+    /// Serve https, with this certificate (PEM). Needs --tls-key.
+    #[clap(long, value_parser, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// The private key (PEM) for --tls-cert.
+    #[clap(long, value_parser, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// If there's no certificate at --tls-cert yet, make a self-signed
+    /// one, and its key at --tls-key.
+    #[clap(long, requires_all = &["tls_cert", "tls_key"])]
+    tls_self_signed: bool,
+
+    /// Another host name or address for the self-signed certificate,
+    /// such as the one the board is reached at on the network.
+    #[clap(long, requires = "tls_self_signed")]
+    tls_name: Vec<String>,
+
+    /// Make everyone log in, with this password for the GM, who can do
+    /// anything. Without it, anyone who can reach the board can.
+    #[clap(long, env = "SOUNDBOARD_GM_PASSWORD")]
+    gm_password: Option<String>,
+
+    /// A password for players, who can only play and stop clips from
+    /// the --player-collection collections.
+    #[clap(long, env = "SOUNDBOARD_PLAYER_PASSWORD")]
+    player_password: Option<String>,
+
+    /// The name of a collection which players can play from.
+    #[clap(long)]
+    player_collection: Vec<String>,
+
+    /// List the sound output devices, and exit.
+    #[clap(long)]
+    list_devices: bool,
+
+    /// The name of the sound output device to play on, as given by
+    /// --list-devices.
+    #[clap(long)]
+    output_device: Option<String>,
+
+    /// The sound output buffer size, in frames. Smaller means lower
+    /// latency, but may crackle.
+    #[clap(long, value_parser)]
+    buffer_size: Option<u32>,
+
+    /// The sample rate to mix at. By default, the sound card's own
+    /// rate, or 48000 with discord, which needs that.
+    #[clap(long, value_parser)]
+    sample_rate: Option<u32>,
+
+    /// With a discord token, play the sounds locally too.
+    #[clap(long)]
+    monitor: bool,
+
+    /// Gain for local playback.
+    #[clap(long, value_parser = parse_gain, default_value = "1.0")]
+    local_gain: f32,
+
+    /// Gain for what's sent to discord.
+    #[clap(long, value_parser = parse_gain, default_value = "1.0")]
+    discord_gain: f32,
+
+    /// How much audio to keep buffered for discord, in milliseconds.
+    /// Raise it if GET /output shows discord underrunning.
+    #[clap(long, value_parser, default_value = "60")]
+    discord_latency_ms: u64,
+
+    /// Gain for recordings.
+    #[clap(long, value_parser = parse_gain, default_value = "1.0")]
+    recording_gain: f32,
+
+    /// Also write the mix out as raw interleaved stereo pcm: to stdout
+    /// with "-", to a unix socket with "unix:<path>", or else to a file
+    /// or named pipe.
+    #[clap(long, value_parser)]
+    pcm_out: Option<pcm::PcmTarget>,
+
+    /// The sample format for --pcm-out.
+    #[clap(long, value_enum, default_value = "s16le")]
+    pcm_format: pcm::PcmFormat,
+
+    /// Gain for --pcm-out.
+    #[clap(long, value_parser = parse_gain, default_value = "1.0")]
+    pcm_gain: f32,
+
+    /// Stream the mix as mp3 at /stream.mp3, for listening remotely.
+    #[clap(long)]
+    stream: bool,
+
+    /// The bitrate of the stream, in kbps.
+    #[clap(long, value_parser, default_value = "128")]
+    stream_bitrate: u32,
+
+    /// Gain for the stream.
+    #[clap(long, value_parser = parse_gain, default_value = "1.0")]
+    stream_gain: f32,
+
+    /// Don't play sounds locally; for running without a sound card.
+    /// Recording still works.
+    #[clap(long, conflicts_with = "discord_token")]
+    null_output: bool,
+
+    /// With --null-output, how many times faster than real time to
+    /// play.
+    #[clap(long, value_parser = parse_speed, default_value = "1.0", requires = "null_output")]
+    null_speed: f64,
+
+    /// Where to put recordings.
+    #[clap(long, value_parser, default_value = ".")]
+    recording_dir: PathBuf,
+
+    /// Start a new recording file every this many minutes.
+    #[clap(long, value_parser)]
+    recording_rotate_minutes: Option<u64>,
+
     /// The bot's api token, for discord. If not given, plays back the
     /// sounds locally.
     #[clap(env)]
     discord_token: Option<String>,
 }
+
+fn parse_speed(s: &str) -> Result<f64, String> {
+    let speed: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    if speed.is_finite() && speed > 0.0 {
+        Ok(speed)
+    } else {
+        Err("must be a positive number".to_string())
+    }
+}
+
+fn parse_gain(s: &str) -> Result<f32, String> {
+    let gain: f32 = s.parse().map_err(|e| format!("{e}"))?;
+    if (0.0..=output::MAX_OUTPUT_GAIN).contains(&gain) {
+        Ok(gain)
+    } else {
+        Err(format!("must be from 0 to {}", output::MAX_OUTPUT_GAIN))
+    }
+}
@@ -1,9 +1,12 @@
 use std::{
-    collections::hash_map::DefaultHasher,
+    collections::{hash_map::DefaultHasher, HashMap},
     hash::{Hash, Hasher},
     path::PathBuf,
 };
 
+/// The name of the optional settings file in a collection directory.
+pub const META_FILE_NAME: &str = "soundboard.json";
+
 #[derive(Default, Clone, Debug)]
 pub struct Library {
     pub collections: Vec<Collection>,
@@ -17,6 +20,24 @@ impl Library {
     pub fn collection(&self, coll_id: u64) -> Option<&Collection> {
         self.collections.iter().find(|coll| coll.id == coll_id)
     }
+
+    /// Read every collection's directory again, picking up clips and
+    /// settings which have changed since. A collection which can't be
+    /// read stays as it was, and its error is returned alongside.
+    pub fn rescan(&self) -> (Library, Vec<(PathBuf, std::io::Error)>) {
+        let mut library = Library::default();
+        let mut errors = vec![];
+        for coll in self.collections.iter() {
+            match Collection::from_dir(&coll.directory, coll.kind) {
+                Ok(rescanned) => library.add_collection(rescanned),
+                Err(e) => {
+                    errors.push((coll.directory.clone(), e));
+                    library.add_collection(coll.clone());
+                }
+            }
+        }
+        (library, errors)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -26,9 +47,43 @@ pub struct Collection {
     pub directory: PathBuf,
     pub clips: Vec<Clip>,
     pub kind: CollectionKind,
+    /// Effects on this collection's own bus, in order.
+    pub effects: Vec<Effect>,
+    /// How many of this collection's clips may play at once.
+    pub max_voices: Option<usize>,
+    pub voice_stealing: VoiceStealing,
+}
+
+/// What to do when starting a clip would go over a voice limit.
+#[derive(Copy, Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub enum VoiceStealing {
+    /// Stop the instance which started first.
+    Oldest,
+    /// Stop the instance with the lowest gain.
+    Quietest,
+    /// Don't start the new instance.
+    Refuse,
 }
 
-#[derive(Copy, Clone, Debug, serde::Serialize, Eq, PartialEq)]
+impl Default for VoiceStealing {
+    fn default() -> Self {
+        VoiceStealing::Oldest
+    }
+}
+
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    serde::Serialize,
+    serde::Deserialize,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    Hash,
+    utoipa::ToSchema,
+)]
 pub enum CollectionKind {
     Drops,
     BackgroundMusic,
@@ -37,6 +92,14 @@ pub enum CollectionKind {
     Ambience,
 }
 impl CollectionKind {
+    pub const ALL: [CollectionKind; 5] = [
+        CollectionKind::Drops,
+        CollectionKind::BackgroundMusic,
+        CollectionKind::BattleMusic,
+        CollectionKind::Fx,
+        CollectionKind::Ambience,
+    ];
+
     pub fn loop_playback(&self) -> bool {
         match self {
             CollectionKind::Drops | CollectionKind::Fx => false,
@@ -68,22 +131,282 @@ impl CollectionKind {
     }
 }
 
+/// Optional settings for a collection, read from its `soundboard.json`.
+#[derive(Default, Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct CollectionMeta {
+    /// Per-clip settings, keyed by file name.
+    pub clips: HashMap<String, ClipMeta>,
+    pub effects: Vec<Effect>,
+    pub max_voices: Option<usize>,
+    pub voice_stealing: VoiceStealing,
+}
+
+#[derive(Default, Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct ClipMeta {
+    pub tempo: Option<Tempo>,
+    /// The file name of another clip in the same collection, played
+    /// right before this one starts.
+    pub stinger: Option<String>,
+    pub tags: Vec<String>,
+    /// Default pan, from -1 (left) to 1 (right).
+    pub pan: Option<f64>,
+    /// Default position; overridden by `pan`.
+    pub position: Option<Position>,
+    /// How many instances of this clip may play at once.
+    pub max_voices: Option<usize>,
+}
+
+#[derive(Copy, Clone, Debug, serde::Deserialize)]
+pub struct Tempo {
+    pub bpm: f64,
+    #[serde(default = "default_beats_per_bar")]
+    pub beats_per_bar: u32,
+    /// Seconds from the start of the file to the first downbeat.
+    #[serde(default)]
+    pub offset: f64,
+}
+
+fn default_beats_per_bar() -> u32 {
+    4
+}
+
+/// An audio effect on a bus.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "type")]
+pub enum Effect {
+    Filter(FilterSettings),
+    Reverb(ReverbSettings),
+    Delay(DelaySettings),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+pub enum FilterMode {
+    LowPass,
+    BandPass,
+    HighPass,
+    Notch,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct FilterSettings {
+    pub mode: FilterMode,
+    /// In Hz
+    pub cutoff: f64,
+    /// From 0 to 1
+    pub resonance: f64,
+    /// From 0 (dry) to 1 (wet)
+    pub mix: f64,
+}
+
+impl Default for FilterSettings {
+    fn default() -> Self {
+        FilterSettings {
+            mode: FilterMode::LowPass,
+            cutoff: 20000.0,
+            resonance: 0.0,
+            mix: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct ReverbSettings {
+    pub feedback: f64,
+    pub damping: f64,
+    pub mix: f64,
+}
+
+impl Default for ReverbSettings {
+    fn default() -> Self {
+        ReverbSettings {
+            feedback: 0.9,
+            damping: 0.1,
+            mix: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct DelaySettings {
+    /// In seconds
+    pub delay_time: f64,
+    pub feedback: f64,
+    pub mix: f64,
+}
+
+impl Default for DelaySettings {
+    fn default() -> Self {
+        DelaySettings {
+            delay_time: 0.5,
+            feedback: 0.5,
+            mix: 0.5,
+        }
+    }
+}
+
+/// Where a sound is, relative to a listener at the origin. `x` goes
+/// from left to right, and `y` away from the listener. A distance of 1
+/// is "close by".
+#[derive(Copy, Clone, Debug, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Where a sound sits in the stereo field: either a plain pan, from -1
+/// (left) to 1 (right), or a position which sets both pan and distance.
+#[derive(Copy, Clone, Debug)]
+pub enum Placement {
+    Pan(f64),
+    Position(Position),
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Placement::Pan(0.0)
+    }
+}
+
+impl Placement {
+    /// A pan from -1 to 1, or a position somewhere finite.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Placement::Pan(pan) => (-1.0..=1.0).contains(pan),
+            Placement::Position(pos) => pos.x.is_finite() && pos.y.is_finite(),
+        }
+    }
+
+    pub fn pan(&self) -> f64 {
+        match self {
+            Placement::Pan(pan) => *pan,
+            // The direction it's coming from; how far away it is
+            // doesn't matter.
+            Placement::Position(pos) => {
+                let distance = pos.x.hypot(pos.y);
+                if distance > 0.0 {
+                    (pos.x / distance).clamp(-1.0, 1.0)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+
+    /// How much quieter the sound is because of its distance.
+    pub fn attenuation(&self) -> f64 {
+        match self {
+            Placement::Pan(_) => 1.0,
+            Placement::Position(pos) => 1.0 / (1.0 + pos.x.hypot(pos.y)),
+        }
+    }
+}
+
+impl CollectionMeta {
+    fn from_dir(path: &std::path::Path) -> std::io::Result<Self> {
+        let meta_path = path.join(META_FILE_NAME);
+        if !meta_path.is_file() {
+            return Ok(CollectionMeta::default());
+        }
+
+        let file = std::fs::File::open(meta_path)?;
+        let meta: CollectionMeta = serde_json::from_reader(std::io::BufReader::new(file))?;
+
+        let invalid = |message: &str| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: {message}", path.display()),
+            ))
+        };
+
+        // A limit of no voices at all would make the clips unplayable.
+        let voice_limits =
+            std::iter::once(meta.max_voices).chain(meta.clips.values().map(|clip| clip.max_voices));
+        for max_voices in voice_limits {
+            if max_voices == Some(0) {
+                return invalid("max_voices must be at least 1");
+            }
+        }
+
+        if !meta.effects.iter().all(crate::effects::is_valid) {
+            return invalid("an effect's settings are out of range");
+        }
+
+        for tempo in meta.clips.values().filter_map(|clip| clip.tempo) {
+            if !(tempo.bpm.is_finite() && tempo.bpm > 0.0)
+                || tempo.beats_per_bar == 0
+                || !tempo.offset.is_finite()
+            {
+                return invalid(
+                    "a tempo needs a bpm and beats_per_bar above 0, and a finite offset",
+                );
+            }
+        }
+
+        for clip in meta.clips.values() {
+            let pan = clip.pan.map(Placement::Pan);
+            let position = clip.position.map(Placement::Position);
+            if !pan.into_iter().chain(position).all(|p| p.is_valid()) {
+                return invalid("pan must be from -1 to 1, and positions finite");
+            }
+        }
+
+        Ok(meta)
+    }
+}
+
 impl Collection {
     pub fn from_dir(
         path: impl AsRef<std::path::Path>,
         kind: CollectionKind,
     ) -> std::io::Result<Self> {
         let path = path.as_ref();
+        let meta = CollectionMeta::from_dir(path)?;
         let mut clips = vec![];
         for entry in std::fs::read_dir(path)? {
             let entry = entry?;
-            if entry.file_type()?.is_file() {
+            if entry.file_name() == META_FILE_NAME {
+                continue;
+            }
+
+            let file_type = entry.file_type()?;
+            if file_type.is_file() {
                 clips.push(Clip::from_file(entry.path())?);
+            } else if file_type.is_dir() {
+                let clip = Clip::from_layer_dir(entry.path())?;
+                if !clip.layers.is_empty() {
+                    clips.push(clip);
+                }
             }
         }
 
         clips.sort_by_key(|clip| clip.name.clone());
 
+        let ids_by_name = clips
+            .iter()
+            .map(|clip| (clip.name.clone(), clip.id))
+            .collect::<HashMap<_, _>>();
+        for clip in clips.iter_mut() {
+            if let Some(clip_meta) = meta.clips.get(&clip.name) {
+                clip.tempo = clip_meta.tempo;
+                clip.stinger = clip_meta
+                    .stinger
+                    .as_ref()
+                    .and_then(|name| ids_by_name.get(name).copied());
+                clip.tags = clip_meta.tags.clone();
+                clip.placement = match (clip_meta.pan, clip_meta.position) {
+                    (Some(pan), _) => Some(Placement::Pan(pan)),
+                    (None, Some(position)) => Some(Placement::Position(position)),
+                    (None, None) => None,
+                };
+                clip.max_voices = clip_meta.max_voices;
+            }
+        }
+
         let mut hasher = DefaultHasher::new();
         path.hash(&mut hasher);
         let id = hasher.finish();
@@ -97,6 +420,9 @@ impl Collection {
             directory: path.to_owned(),
             clips,
             kind,
+            effects: meta.effects,
+            max_voices: meta.max_voices,
+            voice_stealing: meta.voice_stealing,
         })
     }
 
@@ -110,6 +436,17 @@ pub struct Clip {
     pub id: u64,
     pub name: String,
     pub path: PathBuf,
+    // The files that make up this clip. A plain clip has a single
+    // layer, its own path. A layered clip is a directory of stems
+    // which are played back in sync.
+    pub layers: Vec<PathBuf>,
+    pub tempo: Option<Tempo>,
+    // The id of a clip in the same collection to play right before
+    // this one.
+    pub stinger: Option<u64>,
+    pub tags: Vec<String>,
+    pub placement: Option<Placement>,
+    pub max_voices: Option<usize>,
 }
 
 impl Clip {
@@ -127,6 +464,48 @@ impl Clip {
                 .map(|os_str| os_str.to_string_lossy().to_string())
                 .unwrap_or_else(|| "<unknown>".to_string()),
             path: path.to_owned(),
+            layers: vec![path.to_owned()],
+            tempo: None,
+            stinger: None,
+            tags: vec![],
+            placement: None,
+            max_voices: None,
         })
     }
+
+    /// A layered clip, made from a directory of stems. Stems are
+    /// ordered by file name; the first is the base layer, and the rest
+    /// are brought in one by one as the intensity rises.
+    fn from_layer_dir(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let mut clip = Clip::from_file(path)?;
+
+        let mut layers = vec![];
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() && is_audio_file(&entry.path()) {
+                layers.push(entry.path());
+            }
+        }
+        layers.sort();
+
+        clip.layers = layers;
+        Ok(clip)
+    }
+
+    pub fn is_layered(&self) -> bool {
+        self.layers.len() > 1
+    }
+}
+
+// Whether a file looks like something we can play, so that notes and
+// the like sitting next to a clip's stems don't become layers.
+fn is_audio_file(path: &std::path::Path) -> bool {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    matches!(
+        extension.as_deref(),
+        Some("mp3" | "ogg" | "oga" | "flac" | "wav")
+    )
 }
@@ -0,0 +1,626 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use kira::{
+    dsp::Frame,
+    manager::backend::{Backend, Renderer},
+};
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+/// What discord wants, and what we render at when there's no sound
+/// card to ask.
+pub const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+/// The most an output can be turned up.
+pub const MAX_OUTPUT_GAIN: f32 = 10.0;
+
+/// Somewhere the mix goes.
+pub trait Sink: Send {
+    /// Take a batch of rendered frames. This is called from the audio
+    /// thread, so it mustn't block; a sink that can't keep up should
+    /// drop frames instead.
+    fn write(&mut self, frames: &[Frame]);
+
+    /// How it's coping, for sinks which buffer.
+    fn stats(&self) -> Option<Arc<SinkStats>> {
+        None
+    }
+
+    /// For sinks which are drained in real time, like a sound card:
+    /// how many more frames it wants right now to keep its buffer as
+    /// full as it likes. These set the pace the mix is rendered at.
+    fn demand(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// How often a buffered output has run dry, and how often it's had to
+/// drop audio because it was full.
+#[derive(Default, Debug)]
+pub struct SinkStats {
+    underruns: AtomicU64,
+    overruns: AtomicU64,
+}
+
+impl SinkStats {
+    pub fn underruns(&self) -> u64 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    pub fn overruns(&self) -> u64 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    fn underrun(&self) {
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn overrun(&self) {
+        self.overruns.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The gain of one output, which can be changed while playing.
+#[derive(Clone)]
+struct Gain(Arc<AtomicU32>);
+
+impl Gain {
+    fn new(gain: f32) -> Gain {
+        Gain(Arc::new(AtomicU32::new(gain.to_bits())))
+    }
+
+    fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn set(&self, gain: f32) {
+        self.0.store(gain.to_bits(), Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone)]
+struct Control {
+    gain: Gain,
+    stats: Option<Arc<SinkStats>>,
+}
+
+/// How an output is doing.
+#[derive(Debug)]
+pub struct OutputStatus {
+    pub name: String,
+    pub gain: f32,
+    pub stats: Option<Arc<SinkStats>>,
+}
+
+/// Handles on each output, by name, for checking on them and changing
+/// their gain.
+#[derive(Clone, Default)]
+pub struct OutputControls(Arc<BTreeMap<String, Control>>);
+
+impl OutputControls {
+    pub fn list(&self) -> Vec<OutputStatus> {
+        self.0
+            .iter()
+            .map(|(name, control)| OutputStatus {
+                name: name.clone(),
+                gain: control.gain.get(),
+                stats: control.stats.clone(),
+            })
+            .collect()
+    }
+
+    /// Returns false if there's no such output. The gain is clamped
+    /// to between 0 and `MAX_OUTPUT_GAIN`.
+    pub fn set_gain(&self, name: &str, gain: f32) -> bool {
+        let gain = if gain.is_nan() {
+            0.0
+        } else {
+            gain.clamp(0.0, MAX_OUTPUT_GAIN)
+        };
+        match self.0.get(name) {
+            Some(control) => {
+                control.gain.set(gain);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+struct Output {
+    sink: Box<dyn Sink>,
+    gain: Gain,
+}
+
+/// Everything the mix is played to. The mix is rendered once, and each
+/// output gets its own copy at its own gain.
+pub struct Outputs {
+    /// How many times faster than real time to render, when none of the
+    /// outputs set the pace.
+    pub speed: f64,
+    pub sample_rate: u32,
+    outputs: Vec<Output>,
+    controls: BTreeMap<String, Control>,
+}
+
+impl Outputs {
+    pub fn new(speed: f64, sample_rate: u32) -> Outputs {
+        Outputs {
+            speed,
+            sample_rate,
+            outputs: vec![],
+            controls: Default::default(),
+        }
+    }
+
+    pub fn add(&mut self, name: &str, sink: impl Sink + 'static, gain: f32) {
+        let gain = Gain::new(gain);
+        self.controls.insert(
+            name.to_string(),
+            Control {
+                gain: gain.clone(),
+                stats: sink.stats(),
+            },
+        );
+        self.outputs.push(Output {
+            sink: Box::new(sink),
+            gain,
+        });
+    }
+
+    pub fn controls(&self) -> OutputControls {
+        OutputControls(Arc::new(self.controls.clone()))
+    }
+}
+
+/// Renders audio on a thread of its own and hands it to each of the
+/// outputs. It keeps up with whichever output is hungriest, or, if
+/// none of them are drained in real time, with a monotonic clock.
+pub struct OutputBackend {
+    outputs: Option<Outputs>,
+    thread_join_handle: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Backend for OutputBackend {
+    type Settings = Outputs;
+
+    type Error = ();
+
+    fn setup(settings: Self::Settings) -> Result<(Self, u32), Self::Error> {
+        let sample_rate = settings.sample_rate;
+        Ok((
+            OutputBackend {
+                outputs: Some(settings),
+                thread_join_handle: None,
+            },
+            sample_rate,
+        ))
+    }
+
+    fn start(&mut self, mut renderer: Renderer) -> Result<(), Self::Error> {
+        let mut outputs = self.outputs.take().unwrap();
+        let frames_per_sec = outputs.sample_rate as f64 * outputs.speed;
+        let join_handle = std::thread::spawn(move || {
+            let mut last_tick = Instant::now();
+            // Frames the clock says are due, but which haven't been
+            // rendered yet.
+            let mut owed = 0.0;
+            let mut frames = vec![];
+            let mut scaled = vec![];
+            loop {
+                let now = Instant::now();
+                let elapsed = now - last_tick;
+                last_tick = now;
+
+                let demand = outputs
+                    .outputs
+                    .iter()
+                    .filter_map(|output| output.sink.demand())
+                    .max();
+                let count = match demand {
+                    Some(demand) => {
+                        owed = 0.0;
+                        demand
+                    }
+                    None => {
+                        owed += elapsed.as_secs_f64() * frames_per_sec;
+                        let count = owed as usize;
+                        owed -= count as f64;
+                        count
+                    }
+                };
+
+                frames.clear();
+                renderer.on_start_processing();
+                frames.extend((0..count).map(|_| renderer.process()));
+
+                for output in outputs.outputs.iter_mut() {
+                    let gain = output.gain.get();
+                    if gain == 1.0 {
+                        output.sink.write(&frames);
+                    } else {
+                        scaled.clear();
+                        scaled.extend(frames.iter().map(|frame| *frame * gain));
+                        output.sink.write(&scaled);
+                    }
+                }
+
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        });
+
+        self.thread_join_handle = Some(join_handle);
+        Ok(())
+    }
+}
+
+// 1 frame = 2 samples, at 4 bytes each
+const RING_BUFFER_FRAME_BYTES: usize = 8;
+
+/// A ring buffer of interleaved f32 pcm, for relaying the mix to
+/// discord. It aims to hold `target_latency` worth of audio: when it
+/// runs dry it's topped back up to that with silence, and it holds at
+/// most twice that before dropping audio.
+pub fn ring_buffer(
+    sample_rate: u32,
+    target_latency: Duration,
+) -> (RingBufferSink, RingBufferSource) {
+    let target_frames = ((sample_rate as f64 * target_latency.as_secs_f64()) as usize).max(1);
+    let buffer = HeapRb::new(target_frames * 2 * RING_BUFFER_FRAME_BYTES);
+    let (producer, consumer) = buffer.split();
+    let stats = Arc::new(SinkStats::default());
+
+    (
+        RingBufferSink {
+            producer,
+            target_frames,
+            stats: stats.clone(),
+        },
+        RingBufferSource {
+            consumer,
+            stats,
+            primed: false,
+        },
+    )
+}
+
+pub struct RingBufferSink {
+    producer: HeapProducer<u8>,
+    target_frames: usize,
+    stats: Arc<SinkStats>,
+}
+
+impl Sink for RingBufferSink {
+    fn write(&mut self, frames: &[Frame]) {
+        if self.producer.is_empty() {
+            let silence = self.target_frames * RING_BUFFER_FRAME_BYTES;
+            self.producer
+                .push_iter(&mut std::iter::repeat(0).take(silence));
+        }
+
+        for frame in frames {
+            if self.producer.free_len() < RING_BUFFER_FRAME_BYTES {
+                // The reader has fallen behind; drop the rest.
+                self.stats.overrun();
+                break;
+            }
+            let mut bytes = [0u8; RING_BUFFER_FRAME_BYTES];
+            bytes[..4].copy_from_slice(&frame.left.to_le_bytes());
+            bytes[4..].copy_from_slice(&frame.right.to_le_bytes());
+            self.producer.push_slice(&bytes);
+        }
+    }
+
+    fn stats(&self) -> Option<Arc<SinkStats>> {
+        Some(self.stats.clone())
+    }
+
+    fn demand(&self) -> Option<usize> {
+        Some(
+            self.target_frames
+                .saturating_sub(self.producer.len() / RING_BUFFER_FRAME_BYTES),
+        )
+    }
+}
+
+/// The reading end of a `ring_buffer`.
+pub struct RingBufferSource {
+    consumer: HeapConsumer<u8>,
+    stats: Arc<SinkStats>,
+    // Whether anything's been written yet. Until then, running dry
+    // isn't an underrun.
+    primed: bool,
+}
+
+impl RingBufferSource {
+    /// Read as many bytes as are available, up to `buf.len()`.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let read = self.consumer.pop_slice(buf);
+        if read > 0 {
+            self.primed = true;
+        } else if self.primed {
+            self.stats.underrun();
+        }
+        read
+    }
+}
+
+/// Which sound card to play on, and how.
+#[derive(Clone, Debug, Default)]
+pub struct CpalSettings {
+    /// The device's name, as given by `list_devices`. The default
+    /// device if not given.
+    pub device: Option<String>,
+    /// In frames. Smaller is lower latency, but more likely to crackle.
+    pub buffer_size: Option<u32>,
+}
+
+/// The names of the sound output devices, and which is the default.
+pub fn list_devices() -> Result<Vec<(String, bool)>, OutputError> {
+    let host = cpal::default_host();
+    let default_name = host
+        .default_output_device()
+        .and_then(|device| device.name().ok());
+
+    let mut devices = vec![];
+    for device in host.output_devices()? {
+        let name = device.name()?;
+        let is_default = default_name.as_ref() == Some(&name);
+        devices.push((name, is_default));
+    }
+
+    Ok(devices)
+}
+
+/// The sample rate the device plays at by default.
+pub fn device_sample_rate(settings: &CpalSettings) -> Result<u32, OutputError> {
+    let device = find_device(settings)?;
+    Ok(device.default_output_config()?.sample_rate().0)
+}
+
+/// The local sound card. If the device goes away, it's reopened when
+/// it comes back, and until then the sound goes nowhere.
+pub struct CpalSink {
+    producer: HeapProducer<f32>,
+    // How many frames to keep buffered
+    target_frames: usize,
+    // Whether there's a stream draining the buffer. While the device
+    // is gone, it doesn't set the pace.
+    draining: Arc<AtomicBool>,
+    stats: Arc<SinkStats>,
+    // The stream lives on a thread of its own, since it can't be sent
+    // between threads. Dropping this stops it.
+    _stop: mpsc::Sender<()>,
+}
+
+impl CpalSink {
+    pub fn new(settings: CpalSettings, sample_rate: u32) -> Result<CpalSink, OutputError> {
+        // A fifth of a second of stereo audio
+        let buffer = HeapRb::new(sample_rate as usize / 5 * 2);
+        let (producer, consumer) = buffer.split();
+        // The stream gets rebuilt if the device is lost, so it can't
+        // keep the consumer to itself.
+        let consumer = Arc::new(Mutex::new(consumer));
+        let stats = Arc::new(SinkStats::default());
+        let stats_for_stream = stats.clone();
+        // A couple of the device's buffers, and at least 40ms
+        let target_frames =
+            (settings.buffer_size.unwrap_or(0) as usize * 2).max(sample_rate as usize / 25);
+        let draining = Arc::new(AtomicBool::new(false));
+        let draining_for_stream = draining.clone();
+
+        let (result_tx, result_rx) = mpsc::channel();
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        std::thread::spawn(move || {
+            let lost = Arc::new(AtomicBool::new(false));
+            let open = || {
+                build_stream(
+                    &settings,
+                    sample_rate,
+                    consumer.clone(),
+                    stats_for_stream.clone(),
+                    lost.clone(),
+                )
+            };
+
+            let mut stream = match open() {
+                Ok(stream) => {
+                    draining_for_stream.store(true, Ordering::SeqCst);
+                    let _ = result_tx.send(Ok(()));
+                    Some(stream)
+                }
+                Err(e) => {
+                    let _ = result_tx.send(Err(e));
+                    return;
+                }
+            };
+
+            // Until we're stopped, keep an eye on the device.
+            while let Err(mpsc::RecvTimeoutError::Timeout) =
+                stop_rx.recv_timeout(Duration::from_secs(1))
+            {
+                if lost.swap(false, Ordering::SeqCst) {
+                    warn!("Lost the sound output device; waiting for it to come back");
+                    stream = None;
+                    draining_for_stream.store(false, Ordering::SeqCst);
+                }
+
+                if stream.is_none() {
+                    if let Ok(reopened) = open() {
+                        info!("Sound output device is back");
+                        stream = Some(reopened);
+                        draining_for_stream.store(true, Ordering::SeqCst);
+                    }
+                }
+            }
+        });
+        result_rx.recv().unwrap_or(Err(OutputError::StreamThread))?;
+
+        Ok(CpalSink {
+            producer,
+            target_frames,
+            draining,
+            stats,
+            _stop: stop_tx,
+        })
+    }
+}
+
+impl Sink for CpalSink {
+    fn write(&mut self, frames: &[Frame]) {
+        for frame in frames {
+            // Push whole frames only, so the channels never get swapped
+            if self.producer.free_len() < 2 {
+                self.stats.overrun();
+                break;
+            }
+            self.producer.push_slice(&[frame.left, frame.right]);
+        }
+    }
+
+    fn stats(&self) -> Option<Arc<SinkStats>> {
+        Some(self.stats.clone())
+    }
+
+    fn demand(&self) -> Option<usize> {
+        if !self.draining.load(Ordering::SeqCst) {
+            return None;
+        }
+        Some(self.target_frames.saturating_sub(self.producer.len() / 2))
+    }
+}
+
+fn build_stream(
+    settings: &CpalSettings,
+    sample_rate: u32,
+    consumer: Arc<Mutex<HeapConsumer<f32>>>,
+    stats: Arc<SinkStats>,
+    lost: Arc<AtomicBool>,
+) -> Result<cpal::Stream, OutputError> {
+    let device = find_device(settings)?;
+    let default_config = device.default_output_config()?;
+    let config = cpal::StreamConfig {
+        channels: default_config.channels(),
+        sample_rate: cpal::SampleRate(sample_rate),
+        buffer_size: match settings.buffer_size {
+            Some(frames) => cpal::BufferSize::Fixed(frames),
+            None => cpal::BufferSize::Default,
+        },
+    };
+
+    let stream = match default_config.sample_format() {
+        cpal::SampleFormat::F32 => {
+            build_typed_stream::<f32>(&device, &config, consumer, stats, lost)
+        }
+        cpal::SampleFormat::I16 => {
+            build_typed_stream::<i16>(&device, &config, consumer, stats, lost)
+        }
+        cpal::SampleFormat::U16 => {
+            build_typed_stream::<u16>(&device, &config, consumer, stats, lost)
+        }
+    }?;
+    stream.play()?;
+
+    Ok(stream)
+}
+
+fn find_device(settings: &CpalSettings) -> Result<cpal::Device, OutputError> {
+    let host = cpal::default_host();
+    match &settings.device {
+        Some(name) => host
+            .output_devices()?
+            .find(|device| device.name().map_or(false, |n| &n == name))
+            .ok_or_else(|| OutputError::NoSuchDevice(name.clone())),
+        None => host.default_output_device().ok_or(OutputError::NoDevice),
+    }
+}
+
+fn build_typed_stream<T: cpal::Sample>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    consumer: Arc<Mutex<HeapConsumer<f32>>>,
+    stats: Arc<SinkStats>,
+    lost: Arc<AtomicBool>,
+) -> Result<cpal::Stream, OutputError> {
+    let channels = config.channels as usize;
+    // Running dry before anything's been written isn't an underrun.
+    let mut primed = false;
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            // Only contended while a lost device is being replaced
+            let mut consumer = consumer.try_lock();
+            let mut ran_dry = false;
+            for out in data.chunks_mut(channels) {
+                // Silence, if we've run dry
+                let (left, right) = match consumer.as_mut() {
+                    Ok(consumer) if consumer.len() >= 2 => {
+                        primed = true;
+                        // SAFETY: we just checked there are two samples to pop
+                        (consumer.pop().unwrap(), consumer.pop().unwrap())
+                    }
+                    _ => {
+                        ran_dry = true;
+                        (0.0, 0.0)
+                    }
+                };
+                match out {
+                    [mono] => *mono = T::from(&((left + right) / 2.0)),
+                    [l, r, rest @ ..] => {
+                        *l = T::from(&left);
+                        *r = T::from(&right);
+                        for sample in rest {
+                            *sample = T::from(&0.0f32);
+                        }
+                    }
+                    [] => (),
+                }
+            }
+            if ran_dry && primed {
+                stats.underrun();
+            }
+        },
+        move |err| match err {
+            cpal::StreamError::DeviceNotAvailable => lost.store(true, Ordering::SeqCst),
+            err => error!(err = &err as &dyn std::error::Error, "Error playing audio"),
+        },
+    )?;
+
+    Ok(stream)
+}
+
+#[derive(Error, Debug)]
+pub enum OutputError {
+    #[error("No sound output device")]
+    NoDevice,
+
+    #[error("No sound output device called {0:?}")]
+    NoSuchDevice(String),
+
+    #[error("Sound output thread died")]
+    StreamThread,
+
+    #[error(transparent)]
+    Devices(#[from] cpal::DevicesError),
+
+    #[error(transparent)]
+    DeviceName(#[from] cpal::DeviceNameError),
+
+    #[error(transparent)]
+    DefaultStreamConfig(#[from] cpal::DefaultStreamConfigError),
+
+    #[error(transparent)]
+    BuildStream(#[from] cpal::BuildStreamError),
+
+    #[error(transparent)]
+    PlayStream(#[from] cpal::PlayStreamError),
+}
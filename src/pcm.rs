@@ -0,0 +1,183 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    str::FromStr,
+    sync::{mpsc, Arc, Mutex},
+};
+
+use kira::dsp::Frame;
+use tracing::{error, info};
+
+use crate::output::Sink;
+
+/// Where to write raw pcm.
+#[derive(Clone, Debug)]
+pub enum PcmTarget {
+    Stdout,
+    /// A file or a named pipe. A pipe is reopened whenever its reader
+    /// goes away.
+    File(PathBuf),
+    /// A unix socket which we listen on; every client gets the stream.
+    UnixSocket(PathBuf),
+}
+
+impl FromStr for PcmTarget {
+    type Err = std::convert::Infallible;
+
+    /// `-` for stdout, `unix:<path>` for a unix socket, or a path.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "-" => PcmTarget::Stdout,
+            s => match s.strip_prefix("unix:") {
+                Some(path) => PcmTarget::UnixSocket(path.into()),
+                None => PcmTarget::File(s.into()),
+            },
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, clap::ValueEnum)]
+pub enum PcmFormat {
+    /// Signed 16 bit little endian
+    S16le,
+    /// 32 bit float little endian
+    F32le,
+}
+
+/// Interleaved stereo pcm, written out on a thread of its own.
+pub struct PcmSink {
+    format: PcmFormat,
+    sender: mpsc::SyncSender<Vec<u8>>,
+}
+
+impl PcmSink {
+    pub fn new(target: PcmTarget, format: PcmFormat) -> std::io::Result<PcmSink> {
+        // Enough batches for a second or so; after that, we drop audio
+        // rather than hold up the mix.
+        let (sender, receiver) = mpsc::sync_channel(100);
+
+        match target {
+            PcmTarget::Stdout => {
+                std::thread::spawn(move || write_to(std::io::stdout().lock(), receiver));
+            }
+            PcmTarget::File(path) => {
+                std::thread::spawn(move || {
+                    let mut first = true;
+                    loop {
+                        // Opening a named pipe waits for a reader. A file is
+                        // started afresh, then added to if it has to be
+                        // reopened.
+                        let file = OpenOptions::new()
+                            .write(true)
+                            .create(true)
+                            .truncate(first)
+                            .append(!first)
+                            .open(&path);
+                        first = false;
+                        let file = match file {
+                            Ok(file) => file,
+                            Err(e) => {
+                                error!(
+                                    err = &e as &dyn std::error::Error,
+                                    "Error opening pcm output"
+                                );
+                                return;
+                            }
+                        };
+                        info!("Writing pcm to {}", path.display());
+                        if !write_to(file, &receiver) {
+                            return;
+                        }
+                    }
+                });
+            }
+            PcmTarget::UnixSocket(path) => {
+                // Clean up after a previous run
+                let _ = std::fs::remove_file(&path);
+                let listener = UnixListener::bind(&path)?;
+                info!("Serving pcm on {}", path.display());
+
+                let clients = Arc::new(Mutex::new(Vec::<UnixStream>::new()));
+                let clients_for_accept = clients.clone();
+                std::thread::spawn(move || {
+                    for stream in listener.incoming() {
+                        // Writes mustn't wait on a slow client.
+                        let stream = stream.and_then(|stream| {
+                            stream.set_nonblocking(true)?;
+                            Ok(stream)
+                        });
+                        match stream {
+                            Ok(stream) => clients_for_accept.lock().unwrap().push(stream),
+                            Err(e) => {
+                                error!(
+                                    err = &e as &dyn std::error::Error,
+                                    "Error accepting pcm client"
+                                )
+                            }
+                        }
+                    }
+                });
+                std::thread::spawn(move || {
+                    for bytes in receiver {
+                        // Drop any client that's gone away, or that
+                        // can't keep up: its socket buffer is full, and
+                        // a part written batch would misalign its
+                        // samples anyway.
+                        clients.lock().unwrap().retain_mut(|client| {
+                            match client.write_all(&bytes) {
+                                Ok(()) => true,
+                                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                                    info!("Dropping a pcm client that fell behind");
+                                    false
+                                }
+                                Err(_) => false,
+                            }
+                        });
+                    }
+                });
+            }
+        }
+
+        Ok(PcmSink { format, sender })
+    }
+}
+
+// Write everything from `receiver` to `writer`. Returns false once
+// there's nothing more to write, and true if the writer failed.
+fn write_to(mut writer: impl Write, receiver: impl IntoIterator<Item = Vec<u8>>) -> bool {
+    for bytes in receiver {
+        if writer
+            .write_all(&bytes)
+            .and_then(|_| writer.flush())
+            .is_err()
+        {
+            return true;
+        }
+    }
+    false
+}
+
+impl Sink for PcmSink {
+    fn write(&mut self, frames: &[Frame]) {
+        let mut bytes = vec![];
+        for frame in frames {
+            match self.format {
+                PcmFormat::S16le => {
+                    for sample in [frame.left, frame.right] {
+                        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                        bytes.extend(sample.to_le_bytes());
+                    }
+                }
+                PcmFormat::F32le => {
+                    bytes.extend(frame.left.to_le_bytes());
+                    bytes.extend(frame.right.to_le_bytes());
+                }
+            }
+        }
+
+        // If the writer can't keep up, this batch is lost.
+        let _ = self.sender.try_send(bytes);
+    }
+}
@@ -1,107 +1,185 @@
 use hyper::body::Buf;
 use kira::{
+    clock::{ClockHandle, ClockSpeed, ClockTime},
     manager::{
-        backend::{cpal::CpalBackend, Backend},
-        error::PlaySoundError,
+        error::{AddClockError, AddSubTrackError, PlaySoundError},
         AudioManager, AudioManagerSettings,
     },
     sound::{
         static_sound::{PlaybackState, StaticSoundData, StaticSoundHandle, StaticSoundSettings},
         SoundData,
     },
-    tween::Tween,
-    CommandError, Volume,
+    track::{TrackBuilder, TrackHandle, TrackId, TrackRoutes},
+    tween::{Easing, Tween},
+    CommandError, StartTime, Volume,
 };
-use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
-use thiserror::Error;
-use tokio::sync::{
-    broadcast::{error::SendError, Sender},
-    Mutex,
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
 };
+use thiserror::Error;
+use tokio::sync::Mutex;
 
-use crate::model::CollectionKind;
+use crate::{
+    effects::{self, EffectChange, EffectHandle},
+    events::EventBus,
+    model::{Clip, Collection, CollectionKind, Effect, Placement, Tempo, VoiceStealing},
+    output::{OutputBackend, Outputs},
+    scheduler,
+};
 
 pub struct Player {
-    manager: AudioManagerDispatch,
-    playing: HashMap<ClipId, PlayingSound>,
+    manager: Manager,
+    // Keyed by instance id. A clip may be playing several times over.
+    playing: HashMap<u64, PlayingSound>,
+    // Instance ids count up, so a lower id is an older instance.
+    next_instance_id: u64,
     pending_events: Vec<PlayerEvent>,
+    // Started events for sounds scheduled to start later, such as after
+    // a stinger or on a quantized transition. They go out when the clock
+    // gets there.
+    pending_starts: Vec<(ClockTime, PlayerEvent)>,
     coll_gain: HashMap<u64, f64>,
+    // Used to start several sounds on exactly the same sample, such as
+    // the layers of a layered clip.
+    clock: ClockHandle,
+    // Every kind of collection plays into its own bus. A collection
+    // with effects of its own gets a bus too, which feeds its kind's bus.
+    kind_buses: HashMap<CollectionKind, Bus>,
+    coll_buses: HashMap<u64, Bus>,
+    // Collection buses which have been replaced, kept until nothing
+    // plays through them anymore.
+    retired_buses: Vec<Bus>,
+}
+
+struct Bus {
+    track: TrackHandle,
+    effects: Vec<EffectHandle>,
+    // What the effects are set to now
+    settings: Vec<Effect>,
 }
 
-enum AudioManagerDispatch {
-    Cpal(AudioManager<CpalBackend>),
-    RingBuffer(AudioManager<BufferBackend>),
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BusId {
+    Kind(CollectionKind),
+    Collection(u64),
 }
 
+// Wraps kira's manager with the helpers the player needs.
+struct Manager(AudioManager<OutputBackend>);
+
 struct PlayingSound {
-    sound_data: StaticSoundData,
-    handle: StaticSoundHandle,
+    id: ClipId,
+    layers: Vec<Layer>,
     kind: CollectionKind,
+    looping: bool,
+    intensity: f64,
+    tempo: Option<Tempo>,
+    // Relative to the collection gain.
+    gain: f64,
+    placement: Placement,
+    playback_rate: f64,
+    track: TrackId,
+    // Fading out. It's already been reported as stopped, but stays
+    // here until kira is done with it, so a later stop can still cut
+    // the fade short.
+    stopping: bool,
 }
 
-struct BufferBackend {
-    audio_producer: Option<ringbuf::HeapProducer<u8>>,
-    thread_join_handle: Option<std::thread::JoinHandle<()>>,
+struct Layer {
+    sound_data: StaticSoundData,
+    handle: StaticSoundHandle,
 }
 
-struct BufferBackendSettings {
-    audio_producer: ringbuf::HeapProducer<u8>,
-}
+impl PlayingSound {
+    // Layers can be different lengths, so it's playing as long as any
+    // of them are. Otherwise they're all started, paused and stopped
+    // together, so any layer will do.
+    fn state(&self) -> PlaybackState {
+        let mut state = PlaybackState::Stopped;
+        for layer in self.layers.iter() {
+            match layer.handle.state() {
+                PlaybackState::Playing => return PlaybackState::Playing,
+                PlaybackState::Stopped => (),
+                other => state = other,
+            }
+        }
+        state
+    }
 
-impl Backend for BufferBackend {
-    type Settings = BufferBackendSettings;
+    // Where the longest layer is, since that's the one still going.
+    fn position(&self) -> f64 {
+        self.layers
+            .iter()
+            .max_by_key(|layer| layer.sound_data.duration())
+            .map(|layer| layer.handle.position())
+            .unwrap_or_default()
+    }
 
-    type Error = ();
+    fn pause(&mut self, tween: Tween) -> Result<(), CommandError> {
+        for layer in self.layers.iter_mut() {
+            layer.handle.pause(tween)?;
+        }
+        Ok(())
+    }
 
-    fn setup(settings: Self::Settings) -> Result<(Self, u32), Self::Error> {
-        Ok((
-            BufferBackend {
-                audio_producer: Some(settings.audio_producer),
-                thread_join_handle: None,
-            },
-            48000,
-        ))
-    }
-
-    fn start(&mut self, mut renderer: kira::manager::backend::Renderer) -> Result<(), Self::Error> {
-        let mut producer = self.audio_producer.take().unwrap();
-        let mut intermediate = Vec::with_capacity(producer.capacity());
-        let join_handle = std::thread::spawn(move || loop {
-            intermediate.clear();
-
-            // 1 frame = 2 samples, at 4 bytes each
-            let frame_count = producer.free_len() / 8;
-            renderer.on_start_processing();
-            for _ in 0..frame_count {
-                let frame = renderer.process();
-                intermediate.extend(frame.left.to_le_bytes());
-                intermediate.extend(frame.right.to_le_bytes());
-            }
+    fn resume(&mut self, tween: Tween) -> Result<(), CommandError> {
+        for layer in self.layers.iter_mut() {
+            layer.handle.resume(tween)?;
+        }
+        Ok(())
+    }
 
-            producer.push_slice(intermediate.as_mut_slice());
+    fn stop(&mut self, tween: Tween) -> Result<(), CommandError> {
+        for layer in self.layers.iter_mut() {
+            layer.handle.stop(tween)?;
+        }
+        Ok(())
+    }
 
-            std::thread::sleep(Duration::from_millis(10));
-        });
+    fn set_volume(&mut self, coll_gain: f64, tween: Tween) -> Result<(), CommandError> {
+        let layer_count = self.layers.len();
+        for (i, layer) in self.layers.iter_mut().enumerate() {
+            let level = layer_level(i, layer_count, self.intensity);
+            let volume = coll_gain * self.gain * self.placement.attenuation() * level;
+            layer.handle.set_volume(Volume::from(volume), tween)?;
+        }
+        Ok(())
+    }
 
-        self.thread_join_handle = Some(join_handle);
+    fn set_panning(&mut self, tween: Tween) -> Result<(), CommandError> {
+        let panning = kira_panning(self.placement.pan());
+        for layer in self.layers.iter_mut() {
+            layer.handle.set_panning(panning, tween)?;
+        }
         Ok(())
     }
 }
 
+// How loud each layer of a layered clip should be at a given
+// intensity. The base layer is always on; the others fade in one after
+// the other as the intensity goes from 0 to 1.
+fn layer_level(index: usize, layer_count: usize, intensity: f64) -> f64 {
+    if index == 0 || layer_count <= 1 {
+        return 1.0;
+    }
+
+    (intensity * (layer_count - 1) as f64 - (index - 1) as f64).clamp(0.0, 1.0)
+}
+
+// We take pan from -1 (left) to 1 (right); kira goes from 0 to 1.
+fn kira_panning(pan: f64) -> f64 {
+    (pan.clamp(-1.0, 1.0) + 1.0) / 2.0
+}
+
 pub async fn poll_events(
     player_mutex: Arc<Mutex<Player>>,
-    sender: Sender<PlayerEvent>,
+    events: EventBus,
 ) -> Result<(), PlayerError> {
     loop {
-        let events = {
-            let mut player = player_mutex.lock().await;
-            player.poll_events()?
-        };
-
-        for event in events.into_iter() {
-            sender.send(event)?;
-        }
-
+        player_mutex.lock().await.publish_events(&events)?;
         tokio::time::sleep(Duration::from_millis(250)).await;
     }
 }
@@ -109,14 +187,229 @@ pub async fn poll_events(
 #[derive(Debug, Clone)]
 pub enum PlayerEvent {
     Started {
+        instance_id: u64,
         coll_id: u64,
         clip_id: u64,
         duration: f64,
     },
     Stopped {
+        instance_id: u64,
+        coll_id: u64,
+        clip_id: u64,
+    },
+    /// Paused to make way for something of a higher priority
+    Paused {
+        instance_id: u64,
+        coll_id: u64,
+        clip_id: u64,
+    },
+    /// Carrying on after whatever paused it has stopped
+    Resumed {
+        instance_id: u64,
         coll_id: u64,
         clip_id: u64,
     },
+    /// Started over from the beginning
+    Looped {
+        instance_id: u64,
+        coll_id: u64,
+        clip_id: u64,
+    },
+    GainChanged {
+        coll_id: u64,
+        gain: f64,
+    },
+    /// Every instance of a layered clip has a new intensity.
+    IntensityChanged {
+        coll_id: u64,
+        clip_id: u64,
+        intensity: f64,
+    },
+    /// A clip couldn't be played, or couldn't carry on playing.
+    Error {
+        coll_id: u64,
+        clip_id: u64,
+        message: String,
+    },
+    /// Collections or clips have come or gone.
+    LibraryChanged,
+    Scheduled {
+        id: u64,
+        at: SystemTime,
+        action: scheduler::Action,
+    },
+    /// A scheduled action has run, and failed if there's an error.
+    Fired {
+        id: u64,
+        error: Option<String>,
+    },
+    Cancelled {
+        id: u64,
+    },
+    EmitterStarted {
+        id: u64,
+        coll_id: u64,
+    },
+    Emitted {
+        id: u64,
+        coll_id: u64,
+        clip_id: u64,
+    },
+    EmitterStopped {
+        id: u64,
+    },
+}
+
+/// A clip that's just been started.
+#[derive(Debug, Clone)]
+pub struct Instance {
+    pub id: u64,
+    pub coll_id: u64,
+    pub clip_id: u64,
+    /// How long it'll be heard for, once through.
+    pub duration: Duration,
+}
+
+/// Everything the player is doing, at one moment.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub instances: Vec<InstanceState>,
+    /// Collections whose gain has been set
+    pub coll_gains: Vec<(u64, f64)>,
+    pub buses: Vec<(BusId, Vec<Effect>)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct InstanceState {
+    pub id: u64,
+    pub coll_id: u64,
+    pub clip_id: u64,
+    pub kind: CollectionKind,
+    /// Paused to make way for something of a higher priority
+    pub paused: bool,
+    /// In seconds, into the current time through
+    pub position: f64,
+    pub looping: bool,
+    pub gain: f64,
+    pub intensity: f64,
+    pub placement: Placement,
+    pub playback_rate: f64,
+}
+
+/// Where a quantized transition lands.
+#[derive(Copy, Clone, Debug, serde::Deserialize, utoipa::ToSchema)]
+pub enum Quantize {
+    Beat,
+    Bar,
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct PlayOptions {
+    /// Hold the transition until the next beat or bar of the track
+    /// being replaced, if that track has a tempo.
+    pub quantize: Option<Quantize>,
+    /// Multiplies the collection gain.
+    pub gain: Option<f64>,
+    /// Overrides the clip's default placement.
+    pub placement: Option<Placement>,
+    pub fade_in: Option<Duration>,
+    /// Seconds into the clip to start from. Skips the clip's stinger.
+    pub start_offset: Option<f64>,
+    /// Overrides whether the collection kind loops.
+    pub looping: Option<bool>,
+    /// 1 is normal speed; this changes the pitch too.
+    pub playback_rate: Option<f64>,
+    /// Leave whatever is already playing alone, rather than stopping
+    /// or pausing it the way the collection kind normally would.
+    pub no_interrupt: bool,
+}
+
+/// How to fade out stopped sounds.
+#[derive(Default, Copy, Clone, Debug)]
+pub struct StopOptions {
+    /// Defaults to a quick fade for one-shots and a slower one for
+    /// music and ambience.
+    pub fade: Option<Duration>,
+    pub curve: FadeCurve,
+}
+
+/// The shape of a fade: `In` starts gently and finishes quickly,
+/// `Out` the other way around.
+#[derive(Copy, Clone, Debug, serde::Deserialize, utoipa::ToSchema)]
+pub enum FadeCurve {
+    Linear,
+    In,
+    Out,
+    InOut,
+}
+
+impl Default for FadeCurve {
+    fn default() -> Self {
+        FadeCurve::Linear
+    }
+}
+
+impl FadeCurve {
+    fn easing(self) -> Easing {
+        match self {
+            FadeCurve::Linear => Easing::Linear,
+            FadeCurve::In => Easing::InPowi(2),
+            FadeCurve::Out => Easing::OutPowi(2),
+            FadeCurve::InOut => Easing::InOutPowi(2),
+        }
+    }
+}
+
+fn default_stop_fade(kind: CollectionKind) -> Duration {
+    match kind {
+        CollectionKind::Fx | CollectionKind::Drops => Duration::from_millis(200),
+        CollectionKind::BackgroundMusic
+        | CollectionKind::Ambience
+        | CollectionKind::BattleMusic => Duration::from_millis(1000),
+    }
+}
+
+// How to start the layers of a sound.
+struct LayerSettings {
+    start_time: ClockTime,
+    track: TrackId,
+    gain: f64,
+    intensity: f64,
+    pan: f64,
+    playback_rate: f64,
+    fade_in: Option<Duration>,
+    // In seconds
+    start_position: f64,
+}
+
+// Seconds from `position` in the file until the next beat or bar
+// boundary, when it's played at `playback_rate`.
+fn time_to_boundary(tempo: &Tempo, quantize: Quantize, position: f64, playback_rate: f64) -> f64 {
+    let beats = match quantize {
+        Quantize::Beat => 1.0,
+        Quantize::Bar => tempo.beats_per_bar as f64,
+    };
+    let unit = beats * 60.0 / tempo.bpm;
+
+    let since_downbeat = position - tempo.offset;
+    let to_boundary = if since_downbeat < 0.0 {
+        -since_downbeat
+    } else {
+        ((since_downbeat / unit).floor() + 1.0) * unit - since_downbeat
+    };
+    to_boundary / playback_rate
+}
+
+// Does starting something of kind `new` stop or pause something of kind `old`?
+fn is_replaced_by(old: CollectionKind, new: CollectionKind) -> bool {
+    if new.is_exclusive() && old == new {
+        return true;
+    }
+
+    match (old.priority(), new.priority()) {
+        (Some(old_priority), Some(new_priority)) => old_priority < new_priority,
+        _ => false,
+    }
 }
 
 #[derive(Copy, Hash, Eq, PartialEq, Clone, Debug)]
@@ -132,43 +425,109 @@ fn pause_tween() -> Tween {
     }
 }
 
+fn intensity_tween() -> Tween {
+    Tween {
+        duration: Duration::from_millis(2000),
+        ..Default::default()
+    }
+}
+
 impl Player {
-    pub fn new(
-        maybe_ring_buffer: Option<ringbuf::HeapProducer<u8>>,
-    ) -> Result<Player, PlayerError> {
-        let manager = match maybe_ring_buffer {
-            Some(ring_buffer) => AudioManagerDispatch::RingBuffer(
-                AudioManager::<BufferBackend>::new(AudioManagerSettings {
-                    backend_settings: BufferBackendSettings {
-                        audio_producer: ring_buffer,
-                    },
-                    capacities: Default::default(),
-                    main_track_builder: Default::default(),
-                })
-                .unwrap(),
-            ),
-            None => AudioManagerDispatch::Cpal(
-                AudioManager::<CpalBackend>::new(AudioManagerSettings::default())
-                    .map_err(PlayerError::CpalError)?,
-            ),
-        };
+    pub fn new(outputs: Outputs) -> Result<Player, PlayerError> {
+        let mut manager = Manager(
+            AudioManager::<OutputBackend>::new(AudioManagerSettings {
+                backend_settings: outputs,
+                capacities: Default::default(),
+                main_track_builder: Default::default(),
+            })
+            .unwrap(),
+        );
+
+        let clock = manager.add_clock(ClockSpeed::TicksPerSecond(1000.0))?;
+        clock.start()?;
+
+        let mut kind_buses = HashMap::new();
+        for kind in CollectionKind::ALL {
+            let bus = manager.add_bus(&effects::kind_bus_effects(), TrackRoutes::new())?;
+            kind_buses.insert(kind, bus);
+        }
 
         let player = Player {
             manager,
             playing: Default::default(),
+            next_instance_id: 1,
             pending_events: Default::default(),
+            pending_starts: Default::default(),
             coll_gain: Default::default(),
+            clock,
+            kind_buses,
+            coll_buses: Default::default(),
+            retired_buses: vec![],
         };
 
         Ok(player)
     }
 
-    fn clips_to_play_and_pause(&self) -> (Vec<ClipId>, Vec<ClipId>) {
+    /// Give a collection its own bus, if it has effects configured.
+    pub fn add_collection_bus(&mut self, coll: &Collection) -> Result<(), PlayerError> {
+        if coll.effects.is_empty() {
+            return Ok(());
+        }
+
+        // SAFETY: there's a bus for every kind
+        let parent = self.kind_buses.get(&coll.kind).unwrap().track.id();
+        let bus = self
+            .manager
+            .add_bus(&coll.effects, TrackRoutes::parent(parent))?;
+        self.coll_buses.insert(coll.id, bus);
+
+        Ok(())
+    }
+
+    /// Rebuild a collection's bus, for when its effects have changed.
+    /// Whatever's playing carries on through the old one; only new
+    /// instances get the new effects.
+    pub fn replace_collection_bus(&mut self, coll: &Collection) -> Result<(), PlayerError> {
+        if let Some(old) = self.coll_buses.remove(&coll.id) {
+            self.retired_buses.push(old);
+        }
+        self.add_collection_bus(coll)
+    }
+
+    // The track that a collection's sounds should play into.
+    fn track_for(&self, coll_id: u64, kind: CollectionKind) -> TrackId {
+        match self.coll_buses.get(&coll_id) {
+            Some(bus) => bus.track.id(),
+            // SAFETY: there's a bus for every kind
+            None => self.kind_buses.get(&kind).unwrap().track.id(),
+        }
+    }
+
+    /// Change the parameters of the effect at `index` on a bus.
+    pub fn set_effect(
+        &mut self,
+        bus_id: BusId,
+        index: usize,
+        change: &EffectChange,
+    ) -> Result<(), PlayerError> {
+        let bus = match bus_id {
+            BusId::Kind(kind) => self.kind_buses.get_mut(&kind),
+            BusId::Collection(coll_id) => self.coll_buses.get_mut(&coll_id),
+        };
+        let bus = bus.ok_or(PlayerError::NotFound)?;
+        let effect = bus.effects.get_mut(index).ok_or(PlayerError::NotFound)?;
+        effect.apply(change)?;
+        // There are as many settings as effects
+        effects::update_settings(&mut bus.settings[index], change);
+        Ok(())
+    }
+
+    fn clips_to_play_and_pause(&self) -> (Vec<u64>, Vec<u64>) {
         let highest_playing = self
             .playing
             .iter()
             .filter(|(_id, ps)| {
-                ps.handle.state() == PlaybackState::Playing && ps.kind.priority().is_some()
+                !ps.stopping && ps.state() == PlaybackState::Playing && ps.kind.priority().is_some()
             })
             // SAFETY: this unwrap is okay because we just checked it with is_some()
             .max_by_key(|(_id, ps)| ps.kind.priority().unwrap());
@@ -177,7 +536,7 @@ impl Player {
             .playing
             .iter()
             .filter(|(_id, ps)| {
-                ps.handle.state() == PlaybackState::Paused && ps.kind.priority().is_some()
+                !ps.stopping && ps.state() == PlaybackState::Paused && ps.kind.priority().is_some()
             })
             // SAFETY: this unwrap is okay because we just checked it with is_some()
             .max_by_key(|(_id, ps)| ps.kind.priority().unwrap());
@@ -195,34 +554,108 @@ impl Player {
         }
     }
 
+    /// Send out everything that's happened since the last time. Holding
+    /// the player while doing so means nobody sees the player's state
+    /// ahead of its events.
+    pub fn publish_events(&mut self, events: &EventBus) -> Result<(), PlayerError> {
+        for event in self.poll_events()? {
+            events.send(event);
+        }
+        Ok(())
+    }
+
     fn poll_events(&mut self) -> Result<Vec<PlayerEvent>, PlayerError> {
+        let now = self.clock.time().ticks;
+        let (started, later) = std::mem::take(&mut self.pending_starts)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(start_time, _)| start_time.ticks <= now);
+        self.pending_starts = later;
+        self.pending_events
+            .extend(started.into_iter().map(|(_, event)| event));
+
         let (to_play, to_pause) = self.clips_to_play_and_pause();
         for id in to_play.iter() {
             if let Some(playing_sound) = self.playing.get_mut(id) {
-                playing_sound.handle.resume(pause_tween())?
+                playing_sound.resume(pause_tween())?;
+                self.pending_events.push(PlayerEvent::Resumed {
+                    instance_id: *id,
+                    coll_id: playing_sound.id.coll_id,
+                    clip_id: playing_sound.id.clip_id,
+                });
             }
         }
 
         for id in to_pause.iter() {
             if let Some(playing_sound) = self.playing.get_mut(id) {
-                playing_sound.handle.pause(pause_tween())?
+                playing_sound.pause(pause_tween())?;
+                self.pending_events.push(PlayerEvent::Paused {
+                    instance_id: *id,
+                    coll_id: playing_sound.id.coll_id,
+                    clip_id: playing_sound.id.clip_id,
+                });
             }
         }
 
         let mut to_remove = vec![];
         for (id, playing_sound) in self.playing.iter_mut() {
-            match playing_sound.handle.state() {
-                PlaybackState::Stopped if playing_sound.kind.loop_playback() => {
-                    playing_sound.handle = self.manager.play(playing_sound.sound_data.clone())?;
-                    let gain = self.coll_gain.get(&id.coll_id).unwrap_or(&1.0);
-                    playing_sound
-                        .handle
-                        .set_volume(Volume::from(*gain), Tween::default())?;
+            match playing_sound.state() {
+                // Its Stopped event went out when it was stopped.
+                PlaybackState::Stopped if playing_sound.stopping => to_remove.push(*id),
+                PlaybackState::Stopped if playing_sound.looping => {
+                    let gain = self
+                        .coll_gain
+                        .get(&playing_sound.id.coll_id)
+                        .unwrap_or(&1.0);
+                    let sound_data = playing_sound
+                        .layers
+                        .iter()
+                        .map(|layer| layer.sound_data.clone())
+                        .collect::<Vec<_>>();
+                    let layers = self.manager.play_in_sync(
+                        sound_data,
+                        &LayerSettings {
+                            start_time: self.clock.time() + 1,
+                            track: playing_sound.track,
+                            gain: *gain
+                                * playing_sound.gain
+                                * playing_sound.placement.attenuation(),
+                            intensity: playing_sound.intensity,
+                            pan: playing_sound.placement.pan(),
+                            playback_rate: playing_sound.playback_rate,
+                            fade_in: None,
+                            start_position: 0.0,
+                        },
+                    );
+                    match layers {
+                        Ok(layers) => {
+                            playing_sound.layers = layers;
+                            self.pending_events.push(PlayerEvent::Looped {
+                                instance_id: *id,
+                                coll_id: playing_sound.id.coll_id,
+                                clip_id: playing_sound.id.clip_id,
+                            });
+                        }
+                        // Give up on this one, rather than on everything.
+                        Err(e) => {
+                            self.pending_events.push(PlayerEvent::Error {
+                                coll_id: playing_sound.id.coll_id,
+                                clip_id: playing_sound.id.clip_id,
+                                message: e.to_string(),
+                            });
+                            self.pending_events.push(PlayerEvent::Stopped {
+                                instance_id: *id,
+                                coll_id: playing_sound.id.coll_id,
+                                clip_id: playing_sound.id.clip_id,
+                            });
+                            to_remove.push(*id);
+                        }
+                    }
                 }
                 PlaybackState::Stopped => {
                     self.pending_events.push(PlayerEvent::Stopped {
-                        coll_id: id.coll_id,
-                        clip_id: id.clip_id,
+                        instance_id: *id,
+                        coll_id: playing_sound.id.coll_id,
+                        clip_id: playing_sound.id.clip_id,
                     });
                     to_remove.push(*id);
                 }
@@ -233,135 +666,546 @@ impl Player {
         for id in to_remove.into_iter() {
             self.playing.remove(&id);
         }
+        let playing = &self.playing;
+        self.pending_starts.retain(|(_, event)| match event {
+            PlayerEvent::Started { instance_id, .. } => playing.contains_key(instance_id),
+            _ => true,
+        });
+        self.retired_buses
+            .retain(|bus| playing.values().any(|ps| ps.track == bus.track.id()));
 
         let mut res = vec![];
         std::mem::swap(&mut res, &mut self.pending_events);
         Ok(res)
     }
 
-    pub fn playing_clips(&self) -> Vec<(u64, u64)> {
-        self.playing
-            .keys()
-            .map(|id| (id.coll_id, id.clip_id))
-            .collect()
+    pub fn snapshot(&self) -> Snapshot {
+        let mut instances = self
+            .playing
+            .iter()
+            .filter(|(_id, ps)| !ps.stopping)
+            .map(|(id, ps)| InstanceState {
+                id: *id,
+                coll_id: ps.id.coll_id,
+                clip_id: ps.id.clip_id,
+                kind: ps.kind,
+                paused: matches!(ps.state(), PlaybackState::Pausing | PlaybackState::Paused),
+                position: ps.position(),
+                looping: ps.looping,
+                gain: ps.gain,
+                intensity: ps.intensity,
+                placement: ps.placement,
+                playback_rate: ps.playback_rate,
+            })
+            .collect::<Vec<_>>();
+        instances.sort_by_key(|instance| instance.id);
+
+        let mut coll_gains = self
+            .coll_gain
+            .iter()
+            .map(|(coll_id, gain)| (*coll_id, *gain))
+            .collect::<Vec<_>>();
+        coll_gains.sort_by_key(|(coll_id, _)| *coll_id);
+
+        let mut buses = self
+            .kind_buses
+            .iter()
+            .map(|(kind, bus)| (BusId::Kind(*kind), bus.settings.clone()))
+            .chain(
+                self.coll_buses
+                    .iter()
+                    .map(|(coll_id, bus)| (BusId::Collection(*coll_id), bus.settings.clone())),
+            )
+            .collect::<Vec<_>>();
+        buses.sort_by_key(|(bus_id, _)| *bus_id);
+
+        Snapshot {
+            instances,
+            coll_gains,
+            buses,
+        }
+    }
+
+    /// The (instance, collection, clip) ids of everything playing.
+    pub fn playing_instances(&self) -> Vec<(u64, u64, u64)> {
+        let mut instances = self
+            .playing
+            .iter()
+            .filter(|(_id, ps)| !ps.stopping)
+            .map(|(instance_id, ps)| (*instance_id, ps.id.coll_id, ps.id.clip_id))
+            .collect::<Vec<_>>();
+        instances.sort();
+        instances
     }
 
     pub fn play_clip(
         &mut self,
-        coll_id: u64,
-        clip_id: u64,
-        path: PathBuf,
-        kind: CollectionKind,
-    ) -> Result<(), PlayerError> {
-        if kind.is_exclusive() {
-            self.stop_where(|_, playing_sound| playing_sound.kind == kind)?;
+        coll: &Collection,
+        clip: &Clip,
+        options: PlayOptions,
+    ) -> Result<Instance, PlayerError> {
+        let result = self.try_play_clip(coll, clip, options);
+        // Whoever asked finds out, but other controllers should too.
+        if let Err(e) = &result {
+            self.pending_events.push(PlayerEvent::Error {
+                coll_id: coll.id,
+                clip_id: clip.id,
+                message: e.to_string(),
+            });
+        }
+        result
+    }
+
+    fn try_play_clip(
+        &mut self,
+        coll: &Collection,
+        clip: &Clip,
+        options: PlayOptions,
+    ) -> Result<Instance, PlayerError> {
+        let kind = coll.kind;
+        let transition = options
+            .quantize
+            .and_then(|quantize| self.transition_time(kind, quantize));
+        let transition_start = match transition {
+            Some(time) => StartTime::ClockTime(time),
+            None => StartTime::Immediate,
+        };
+
+        if kind.is_exclusive() && !options.no_interrupt {
+            self.stop_where_at(
+                transition_start,
+                &StopOptions::default(),
+                |_, playing_sound| playing_sound.kind == kind && !playing_sound.stopping,
+            )?;
         }
 
-        if let Some(priority) = kind.priority() {
+        if let Some(priority) = kind.priority().filter(|_| !options.no_interrupt) {
             // pause any lower priority tracks
-            for (_id, playing_sound) in self.playing.iter_mut() {
+            for (id, playing_sound) in self.playing.iter_mut() {
+                if playing_sound.stopping {
+                    continue;
+                }
                 if let Some(other_priority) = playing_sound.kind.priority() {
                     if other_priority < priority {
-                        playing_sound.handle.pause(pause_tween())?;
+                        // Anything already paused stays that way, quietly.
+                        if playing_sound.state() == PlaybackState::Playing {
+                            self.pending_events.push(PlayerEvent::Paused {
+                                instance_id: *id,
+                                coll_id: playing_sound.id.coll_id,
+                                clip_id: playing_sound.id.clip_id,
+                            });
+                        }
+                        playing_sound.pause(Tween {
+                            start_time: transition_start,
+                            ..pause_tween()
+                        })?;
                     }
                 }
             }
         }
 
-        let sound_data = StaticSoundData::from_file(path, StaticSoundSettings::default())?;
-        let duration = sound_data.duration();
-        let mut handle = self.manager.play(sound_data.clone())?;
+        let mut start_time = transition.unwrap_or_else(|| self.clock.time() + 1);
+        let stinger = clip
+            .stinger
+            .filter(|_| options.start_offset.is_none())
+            .and_then(|stinger_id| coll.clip(stinger_id));
+        let mut clip_options = options.clone();
+        if let Some(stinger) = stinger {
+            // Or the stinger would play on its own.
+            self.check_room(coll, &[stinger, clip])?;
+
+            // The fade in is for whatever is heard first.
+            let stinger_options = PlayOptions {
+                start_offset: None,
+                ..options.clone()
+            };
+            let stinger = self.start_sound(coll, stinger, false, start_time, &stinger_options)?;
+            start_time += stinger.duration.as_millis() as u64;
+            clip_options.fade_in = None;
+        }
+
+        let looping = options.looping.unwrap_or_else(|| kind.loop_playback());
+        self.start_sound(coll, clip, looping, start_time, &clip_options)
+    }
+
+    // When a quantized transition to something of `kind` should happen:
+    // the next boundary of the tempo-tagged track it replaces.
+    fn transition_time(&self, kind: CollectionKind, quantize: Quantize) -> Option<ClockTime> {
+        let delay = self
+            .playing
+            .values()
+            .filter(|ps| {
+                !ps.stopping
+                    && ps.state() == PlaybackState::Playing
+                    && is_replaced_by(ps.kind, kind)
+            })
+            .find_map(|ps| {
+                Some(time_to_boundary(
+                    &ps.tempo?,
+                    quantize,
+                    ps.position(),
+                    ps.playback_rate,
+                ))
+            })?;
 
-        let gain = self.coll_gain.get(&coll_id).unwrap_or(&1.0);
-        handle.set_volume(Volume::from(*gain), Tween::default())?;
+        Some(self.clock.time() + (delay * 1000.0).round() as u64)
+    }
 
+    fn start_sound(
+        &mut self,
+        coll: &Collection,
+        clip: &Clip,
+        looping: bool,
+        start_time: ClockTime,
+        options: &PlayOptions,
+    ) -> Result<Instance, PlayerError> {
+        let coll_id = coll.id;
+        let kind = coll.kind;
+        let sound_data = clip
+            .layers
+            .iter()
+            .map(|path| StaticSoundData::from_file(path, StaticSoundSettings::default()))
+            .collect::<Result<Vec<_>, _>>()?;
+        // Only once it's loaded, so a clip that won't play doesn't
+        // stop anything.
+        self.make_room(coll, clip)?;
+        let start_position = options.start_offset.unwrap_or(0.0);
+        let playback_rate = options.playback_rate.unwrap_or(1.0);
+        // How long this will actually be heard for
+        let duration = sound_data
+            .iter()
+            .map(|sd| sd.duration())
+            .max()
+            .unwrap_or_default()
+            .saturating_sub(Duration::from_secs_f64(start_position))
+            .div_f64(playback_rate);
+
+        let coll_gain = self.coll_gain.get(&coll_id).unwrap_or(&1.0);
+        let gain = options.gain.unwrap_or(1.0);
+        let placement = options.placement.or(clip.placement).unwrap_or_default();
+        let intensity = 0.0;
+        let track = self.track_for(coll_id, kind);
+        let layers = self.manager.play_in_sync(
+            sound_data,
+            &LayerSettings {
+                start_time,
+                track,
+                gain: *coll_gain * gain * placement.attenuation(),
+                intensity,
+                pan: placement.pan(),
+                playback_rate,
+                fade_in: options.fade_in,
+                start_position,
+            },
+        )?;
+
+        let instance_id = self.next_instance_id;
+        self.next_instance_id += 1;
         self.playing.insert(
-            ClipId { coll_id, clip_id },
+            instance_id,
             PlayingSound {
-                sound_data,
-                handle,
+                id: ClipId {
+                    coll_id,
+                    clip_id: clip.id,
+                },
+                layers,
                 kind,
+                looping,
+                intensity,
+                tempo: clip.tempo,
+                gain,
+                placement,
+                playback_rate,
+                track,
+                stopping: false,
             },
         );
-        self.pending_events.push(PlayerEvent::Started {
+        let started = PlayerEvent::Started {
+            instance_id,
             coll_id,
-            clip_id,
+            clip_id: clip.id,
             duration: duration.as_secs_f64(),
-        });
+        };
+        if start_time.ticks > self.clock.time().ticks + 1 {
+            self.pending_starts.push((start_time, started));
+        } else {
+            self.pending_events.push(started);
+        }
+
+        Ok(Instance {
+            id: instance_id,
+            coll_id,
+            clip_id: clip.id,
+            duration,
+        })
+    }
+
+    // Stop instances to keep within the clip's and the collection's
+    // voice limits, making room for one more.
+    fn make_room(&mut self, coll: &Collection, clip: &Clip) -> Result<(), PlayerError> {
+        let clip_id = ClipId {
+            coll_id: coll.id,
+            clip_id: clip.id,
+        };
+        if let Some(max_voices) = clip.max_voices {
+            self.steal_voices(max_voices, coll.voice_stealing, |ps| ps.id == clip_id)?;
+        }
+
+        if let Some(max_voices) = coll.max_voices {
+            self.steal_voices(max_voices, coll.voice_stealing, |ps| {
+                ps.id.coll_id == coll.id
+            })?;
+        }
 
         Ok(())
     }
 
-    pub fn stop_all(&mut self) -> Result<(), PlayerError> {
-        self.stop_where(|_, _| true)
+    // Whether `clips` can all be started without going over a voice
+    // limit which refuses new instances.
+    fn check_room(&self, coll: &Collection, clips: &[&Clip]) -> Result<(), PlayerError> {
+        if !matches!(coll.voice_stealing, VoiceStealing::Refuse) {
+            return Ok(());
+        }
+
+        let voices = |pred: &dyn Fn(&ClipId) -> bool| {
+            self.playing
+                .values()
+                .filter(|ps| !ps.stopping && pred(&ps.id))
+                .count()
+        };
+        for clip in clips {
+            if let Some(max_voices) = clip.max_voices {
+                let starting = clips.iter().filter(|other| other.id == clip.id).count();
+                let playing = voices(&|id| id.coll_id == coll.id && id.clip_id == clip.id);
+                if playing + starting > max_voices {
+                    return Err(PlayerError::NoFreeVoice);
+                }
+            }
+        }
+        if let Some(max_voices) = coll.max_voices {
+            if voices(&|id| id.coll_id == coll.id) + clips.len() > max_voices {
+                return Err(PlayerError::NoFreeVoice);
+            }
+        }
+
+        Ok(())
     }
 
-    pub fn stop_coll(&mut self, coll_id: u64) -> Result<(), PlayerError> {
-        self.stop_where(|id, _| id.coll_id == coll_id)
+    fn steal_voices(
+        &mut self,
+        max_voices: usize,
+        policy: VoiceStealing,
+        pred: impl Fn(&PlayingSound) -> bool,
+    ) -> Result<(), PlayerError> {
+        let max_voices = max_voices.max(1);
+        let mut voices = self
+            .playing
+            .iter()
+            .filter(|(_id, ps)| !ps.stopping && pred(ps))
+            .map(|(id, ps)| (*id, ps.gain * ps.placement.attenuation()))
+            .collect::<Vec<_>>();
+        if voices.len() < max_voices {
+            return Ok(());
+        }
+
+        match policy {
+            VoiceStealing::Refuse => return Err(PlayerError::NoFreeVoice),
+            VoiceStealing::Oldest => voices.sort_by_key(|(id, _)| *id),
+            VoiceStealing::Quietest => voices.sort_by(|(a_id, a_gain), (b_id, b_gain)| {
+                a_gain
+                    .partial_cmp(b_gain)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .then(a_id.cmp(b_id))
+            }),
+        }
+
+        let to_stop = voices
+            .iter()
+            .take(voices.len() + 1 - max_voices)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        self.stop_where(&StopOptions::default(), |id, _| to_stop.contains(&id))
     }
 
-    pub fn stop_clip(&mut self, coll_id: u64, clip_id: u64) -> Result<(), PlayerError> {
-        self.stop_where(|id, _| id.coll_id == coll_id && id.clip_id == clip_id)
+    pub fn stop_all(&mut self, options: &StopOptions) -> Result<(), PlayerError> {
+        self.stop_where(options, |_, _| true)
+    }
+
+    pub fn stop_coll(&mut self, coll_id: u64, options: &StopOptions) -> Result<(), PlayerError> {
+        self.stop_where(options, |_, ps| ps.id.coll_id == coll_id)
+    }
+
+    /// Stop every instance of a clip.
+    pub fn stop_clip(
+        &mut self,
+        coll_id: u64,
+        clip_id: u64,
+        options: &StopOptions,
+    ) -> Result<(), PlayerError> {
+        self.stop_where(options, |_, ps| {
+            ps.id.coll_id == coll_id && ps.id.clip_id == clip_id
+        })
+    }
+
+    pub fn stop_instance(
+        &mut self,
+        instance_id: u64,
+        options: &StopOptions,
+    ) -> Result<(), PlayerError> {
+        if !self.playing.contains_key(&instance_id) {
+            return Err(PlayerError::NotFound);
+        }
+
+        self.stop_where(options, |id, _| id == instance_id)
     }
 
     fn stop_where(
         &mut self,
-        pred: impl Fn(&ClipId, &PlayingSound) -> bool,
+        options: &StopOptions,
+        pred: impl Fn(u64, &PlayingSound) -> bool,
+    ) -> Result<(), PlayerError> {
+        self.stop_where_at(StartTime::Immediate, options, pred)
+    }
+
+    fn stop_where_at(
+        &mut self,
+        start_time: StartTime,
+        options: &StopOptions,
+        pred: impl Fn(u64, &PlayingSound) -> bool,
     ) -> Result<(), PlayerError> {
-        let mut to_remove = vec![];
         for (id, playing_sound) in self.playing.iter_mut() {
-            if !(pred)(id, playing_sound) {
+            if !(pred)(*id, playing_sound) {
                 continue;
             }
 
-            playing_sound.handle.stop(Tween {
-                duration: match playing_sound.kind {
-                    CollectionKind::Fx | CollectionKind::Drops => Duration::from_millis(200),
-                    CollectionKind::BackgroundMusic
-                    | CollectionKind::Ambience
-                    | CollectionKind::BattleMusic => Duration::from_millis(1000),
-                },
-                ..Default::default()
+            // Stopping something that's already fading out replaces
+            // its fade.
+            playing_sound.stop(Tween {
+                duration: options
+                    .fade
+                    .unwrap_or_else(|| default_stop_fade(playing_sound.kind)),
+                start_time,
+                easing: options.curve.easing(),
             })?;
 
-            self.pending_events.push(PlayerEvent::Stopped {
-                coll_id: id.coll_id,
-                clip_id: id.clip_id,
-            });
-            to_remove.push(*id);
-        }
-
-        for id in to_remove.into_iter() {
-            self.playing.remove(&id);
+            if !playing_sound.stopping {
+                playing_sound.stopping = true;
+                // Nobody heard it start, so nobody needs to hear it stop.
+                let len = self.pending_starts.len();
+                self.pending_starts.retain(|(_, event)| {
+                    !matches!(event, PlayerEvent::Started { instance_id, .. } if instance_id == id)
+                });
+                if self.pending_starts.len() < len {
+                    continue;
+                }
+                self.pending_events.push(PlayerEvent::Stopped {
+                    instance_id: *id,
+                    coll_id: playing_sound.id.coll_id,
+                    clip_id: playing_sound.id.clip_id,
+                });
+            }
         }
 
         Ok(())
     }
 
     pub fn set_gain(&mut self, coll_id: u64, gain: f64) -> Result<(), PlayerError> {
-        for (id, ps) in self.playing.iter_mut() {
-            if id.coll_id != coll_id {
+        self.fade_gain(coll_id, gain, Tween::default().duration)
+    }
+
+    /// Like `set_gain`, but ramps to the new gain over `duration`.
+    pub fn fade_gain(
+        &mut self,
+        coll_id: u64,
+        gain: f64,
+        duration: Duration,
+    ) -> Result<(), PlayerError> {
+        for ps in self.playing.values_mut() {
+            if ps.id.coll_id != coll_id {
                 continue;
             }
-            ps.handle.set_volume(Volume::from(gain), Tween::default())?;
+            ps.set_volume(
+                gain,
+                Tween {
+                    duration,
+                    ..Default::default()
+                },
+            )?;
         }
 
         self.coll_gain.insert(coll_id, gain);
+        self.pending_events
+            .push(PlayerEvent::GainChanged { coll_id, gain });
+        Ok(())
+    }
+
+    /// Set the intensity of every instance of a playing layered clip,
+    /// between 0 and 1. Higher intensities fade in more of the clip's
+    /// layers. NotFound if the clip isn't playing.
+    pub fn set_intensity(
+        &mut self,
+        coll_id: u64,
+        clip_id: u64,
+        intensity: f64,
+    ) -> Result<(), PlayerError> {
+        let gain = *self.coll_gain.get(&coll_id).unwrap_or(&1.0);
+        let id = ClipId { coll_id, clip_id };
+        let intensity = intensity.clamp(0.0, 1.0);
+        let mut found = false;
+        for ps in self
+            .playing
+            .values_mut()
+            .filter(|ps| ps.id == id && !ps.stopping)
+        {
+            ps.intensity = intensity;
+            ps.set_volume(gain, intensity_tween())?;
+            found = true;
+        }
+        if !found {
+            return Err(PlayerError::NotFound);
+        }
+
+        self.pending_events.push(PlayerEvent::IntensityChanged {
+            coll_id,
+            clip_id,
+            intensity,
+        });
+        Ok(())
+    }
+
+    /// Move every instance of a playing clip around the stereo field.
+    pub fn set_placement(
+        &mut self,
+        coll_id: u64,
+        clip_id: u64,
+        placement: Placement,
+    ) -> Result<(), PlayerError> {
+        let gain = *self.coll_gain.get(&coll_id).unwrap_or(&1.0);
+        let id = ClipId { coll_id, clip_id };
+        for ps in self.playing.values_mut().filter(|ps| ps.id == id) {
+            ps.placement = placement;
+            ps.set_panning(Tween::default())?;
+            ps.set_volume(gain, Tween::default())?;
+        }
+
         Ok(())
     }
 }
 
 #[derive(Error, Debug)]
 pub enum PlayerError {
+    #[error("No such collection or clip")]
+    NotFound,
+
+    #[error("Too many instances already playing")]
+    NoFreeVoice,
+
     #[error(transparent)]
     Io(#[from] std::io::Error),
 
     #[error(transparent)]
     FromFileError(#[from] kira::sound::FromFileError),
 
-    #[error(transparent)]
-    CpalError(<CpalBackend as Backend>::Error),
-
     #[error(transparent)]
     PlaySound(#[from] PlaySoundError<<StaticSoundData as SoundData>::Error>),
 
@@ -369,14 +1213,149 @@ pub enum PlayerError {
     Command(#[from] CommandError),
 
     #[error(transparent)]
-    SendError(#[from] SendError<PlayerEvent>),
+    AddClock(#[from] AddClockError),
+
+    #[error(transparent)]
+    AddSubTrack(#[from] AddSubTrackError),
 }
 
-impl AudioManagerDispatch {
+impl Manager {
     fn play<D: SoundData>(&mut self, sound_data: D) -> Result<D::Handle, PlaySoundError<D::Error>> {
-        match self {
-            AudioManagerDispatch::Cpal(mgr) => mgr.play(sound_data),
-            AudioManagerDispatch::RingBuffer(mgr) => mgr.play(sound_data),
+        self.0.play(sound_data)
+    }
+
+    fn add_clock(&mut self, speed: ClockSpeed) -> Result<ClockHandle, AddClockError> {
+        self.0.add_clock(speed)
+    }
+
+    fn add_sub_track(&mut self, builder: TrackBuilder) -> Result<TrackHandle, AddSubTrackError> {
+        self.0.add_sub_track(builder)
+    }
+
+    fn add_bus(
+        &mut self,
+        effects: &[Effect],
+        routes: TrackRoutes,
+    ) -> Result<Bus, AddSubTrackError> {
+        let mut builder = TrackBuilder::new().routes(routes);
+        let handles = effects::add_effects(&mut builder, effects);
+        let track = self.add_sub_track(builder)?;
+        Ok(Bus {
+            track,
+            effects: handles,
+            settings: effects.to_vec(),
+        })
+    }
+
+    /// Play several sounds so that they start on the same sample. They
+    /// are held until `settings.start_time`, a tick of the player's
+    /// clock, which all of them see at once.
+    fn play_in_sync(
+        &mut self,
+        sound_data: Vec<StaticSoundData>,
+        settings: &LayerSettings,
+    ) -> Result<Vec<Layer>, PlayerError> {
+        let layer_count = sound_data.len();
+        let fade_in = settings.fade_in.map(|duration| Tween {
+            duration,
+            ..Default::default()
+        });
+        let mut layers = vec![];
+        for (i, sound_data) in sound_data.into_iter().enumerate() {
+            let volume = settings.gain * layer_level(i, layer_count, settings.intensity);
+            let handle = self.play(sound_data.with_modified_settings(|s| {
+                s.start_time(settings.start_time)
+                    .start_position(settings.start_position)
+                    .track(settings.track)
+                    .volume(volume)
+                    .panning(kira_panning(settings.pan))
+                    .playback_rate(settings.playback_rate)
+                    .fade_in_tween(fade_in)
+            }))?;
+            layers.push(Layer { sound_data, handle });
         }
+
+        Ok(layers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::Path,
+        sync::{Arc, Mutex},
+        time::Instant,
+    };
+
+    use kira::dsp::Frame;
+
+    use super::*;
+    use crate::output::Sink;
+
+    // Keeps everything it's given.
+    struct Capture(Arc<Mutex<Vec<Frame>>>);
+
+    impl Sink for Capture {
+        fn write(&mut self, frames: &[Frame]) {
+            self.0.lock().unwrap().extend_from_slice(frames);
+        }
+    }
+
+    // A quarter of a second of a stereo sine wave.
+    fn write_tone(path: &Path, sample_rate: u32) {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for i in 0..sample_rate / 4 {
+            let t = i as f64 / sample_rate as f64;
+            let sample = ((t * 440.0 * std::f64::consts::TAU).sin() * 16000.0) as i16;
+            writer.write_sample(sample).unwrap();
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[test]
+    fn plays_a_clip_through_the_null_output() {
+        let dir = std::env::temp_dir().join(format!("soundboard-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_tone(&dir.join("tone.wav"), 48000);
+        let coll = Collection::from_dir(&dir, CollectionKind::Fx).unwrap();
+
+        // Ten times faster than real time, like --null-speed 10
+        let captured = Arc::new(Mutex::new(vec![]));
+        let mut outputs = Outputs::new(10.0, 48000);
+        outputs.add("capture", Capture(captured.clone()), 1.0);
+        let mut player = Player::new(outputs).unwrap();
+
+        let instance = player
+            .play_clip(&coll, &coll.clips[0], PlayOptions::default())
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut events = vec![];
+        while !events
+            .iter()
+            .any(|event| matches!(event, PlayerEvent::Stopped { .. }))
+        {
+            assert!(Instant::now() < deadline, "the clip never finished");
+            std::thread::sleep(Duration::from_millis(10));
+            events.extend(player.poll_events().unwrap());
+        }
+
+        assert!(matches!(
+            events[0],
+            PlayerEvent::Started { instance_id, .. } if instance_id == instance.id
+        ));
+        assert!(captured
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|frame| frame.left.abs() > 0.1));
     }
 }
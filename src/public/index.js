@@ -35,33 +35,121 @@ const KindDisplayName = {
 
 function App(props) {
     const [collections, setCollections] = useState([]);
+    const [library_version, setLibraryVersion] = useState(0);
+    // Players can only play and stop clips, so don't offer them more.
+    const [gm, setGm] = useState(false);
     const bus = useContext(Bus);
 
-    const on_backend_message = (sse_event) => {
-        let event = JSON.parse(sse_event.data);
+    const handle_event = (event) => {
         if (event.Started !== undefined) {
-            bus.emit(`${event.Started.coll_id}/${event.Started.clip_id}`, {event: "Started"});
+            bus.emit(`${event.Started.coll_id}/${event.Started.clip_id}`,
+                     {event: "Started", instance_id: event.Started.instance_id});
         } else if (event.Stopped !== undefined) {
-            bus.emit(`${event.Stopped.coll_id}/${event.Stopped.clip_id}`, {event: "Stopped"});
+            bus.emit(`${event.Stopped.coll_id}/${event.Stopped.clip_id}`,
+                     {event: "Stopped", instance_id: event.Stopped.instance_id});
+        } else if (event.Paused !== undefined) {
+            bus.emit(`${event.Paused.coll_id}/${event.Paused.clip_id}`,
+                     {event: "Paused", instance_id: event.Paused.instance_id});
+        } else if (event.Resumed !== undefined) {
+            bus.emit(`${event.Resumed.coll_id}/${event.Resumed.clip_id}`,
+                     {event: "Resumed", instance_id: event.Resumed.instance_id});
+        } else if (event.IntensityChanged !== undefined) {
+            bus.emit(`${event.IntensityChanged.coll_id}/${event.IntensityChanged.clip_id}`,
+                     {event: "IntensityChanged", intensity: event.IntensityChanged.intensity});
+        } else if (event.GainChanged !== undefined) {
+            bus.emit(`${event.GainChanged.coll_id}/gain`, event.GainChanged.gain);
+        } else if (event.Error !== undefined) {
+            console.warn(event.Error.message);
+            bus.emit(`${event.Error.coll_id}/${event.Error.clip_id}`, {event: "Error"});
+        } else if (event === "LibraryChanged") {
+            bus.emit("library");
+        } else if (event.Scheduled !== undefined) {
+            bus.emit("schedule", {event: "Scheduled", scheduled: event.Scheduled});
+        } else if (event.Fired !== undefined) {
+            if (event.Fired.error !== null) {
+                console.warn(event.Fired.error);
+            }
+            bus.emit("schedule", {event: "Done", id: event.Fired.id});
+        } else if (event.Cancelled !== undefined) {
+            bus.emit("schedule", {event: "Done", id: event.Cancelled.id});
         }
     };
 
     // init effects
     useEffect(() => {
-        fetch('/collection')
+        // Events are numbered. Until the state arrives, hold on to
+        // them; after, skip any the state already includes.
+        let seq = null;
+        let held = [];
+        let clips = [];
+        const on_backend_message = (sse_event) => {
+            if (seq === null) {
+                held.push(sse_event);
+            } else if (Number(sse_event.lastEventId) > seq) {
+                handle_event(JSON.parse(sse_event.data));
+            }
+        };
+
+        // Make everything look like the state says, whatever it looked
+        // like before.
+        const apply_state = (state) => {
+            let playing = {};
+            let paused = {};
+            let intensity = {};
+            state.instances.forEach((instance) => {
+                let key = `${instance.coll_id}/${instance.clip_id}`;
+                playing[key] = [...(playing[key] || []), instance.instance_id];
+                intensity[key] = instance.intensity;
+                if (instance.paused) {
+                    paused[key] = [...(paused[key] || []), instance.instance_id];
+                }
+            });
+            clips.forEach((key) => bus.emit(key, {event: "Playing",
+                                                  instance_ids: playing[key] || [],
+                                                  paused_ids: paused[key] || [],
+                                                  intensity: intensity[key] || 0.0}));
+            state.collections.forEach(({coll_id, gain}) =>
+                bus.emit(`${coll_id}/gain`, gain));
+            seq = state.seq;
+        };
+
+        const event_source = new EventSource("/events");
+        event_source.onmessage = on_backend_message;
+        // We fell too far behind, and the server's starting us over.
+        // Before the first state's arrived, that will do instead.
+        event_source.addEventListener("resync", (sse_event) => {
+            if (seq !== null) {
+                apply_state(JSON.parse(sse_event.data));
+                bus.emit("schedule", {event: "Resync"});
+            }
+        });
+
+        const fetch_collections = () => fetch('/collection')
             .then((response) => response.json())
             .then((data) => {
                 setCollections(data);
-                fetch('/playing')
-                    .then((response) => response.json())
-                    .then((data) => data.forEach(([coll_id, clip_id]) =>
-                        bus.emit(`${coll_id}/${clip_id}`, {event: "Started"})))
+                setLibraryVersion((version) => version + 1);
+                clips = data.flatMap((coll) => coll.clips.map((clip) => `${coll.id}/${clip.id}`));
             });
+        const fetch_state = () => fetch('/state')
+            .then((response) => response.json())
+            .then(apply_state);
 
-        const event_source = new EventSource("/events");
-        event_source.onmessage = on_backend_message;
+        // Everything's drawn afresh, so needs its state again.
+        bus.on("library", () => fetch_collections().then(fetch_state));
+
+        fetch('/session')
+            .then((response) => response.json())
+            .then((session) => setGm(session.role === "Gm"));
+
+        fetch_collections()
+            .then(fetch_state)
+            .then(() => {
+                held.forEach(on_backend_message);
+                held = [];
+            });
 
-        return () => { event_source.close() };
+        return () => { bus.off("library"); event_source.close() };
     }, []);
     
     const stop_all = (e) => {
@@ -72,16 +160,93 @@ function App(props) {
     return e('div.container',
              e('header',
                e('span.fs-1.me-3', "The Soundboard"),
-               e('big', e('b', el('a', { href: '#', onClick: stop_all },
-                                  "STOP ALL")))),
-             e('main',
+               gm && e('big', e('b', el('a', { href: '#', onClick: stop_all },
+                                        "STOP ALL"))),
+               gm && h(Record),
+               gm && h(Schedule)),
+             el('main', { key: `library-${library_version}` },
                collections.map(
                    coll => h(Fragment, null,
                              el('hr'),
                              h(Collection, { id: coll.id,
                                              name: coll.name,
                                              clips: coll.clips,
-                                             kind: KindDisplayName[coll.kind]})))));
+                                             kind: KindDisplayName[coll.kind],
+                                             gm,
+                                             can_emit: gm && (coll.kind === "Fx" || coll.kind === "Ambience")})))));
+}
+
+function Record(props) {
+    const [path, setPath] = useState(null);
+
+    useEffect(() => {
+        fetch('/recording')
+            .then((response) => response.json())
+            .then((data) => setPath(data.path));
+    }, []);
+
+    const toggle = (e) => {
+        if (path) {
+            fetch('/recording/stop', { method: 'POST' }).then(() => setPath(null));
+        } else {
+            fetch('/recording/start', { method: 'POST' })
+                .then((response) => response.ok ? response.json() : { path: null })
+                .then((data) => setPath(data.path));
+        }
+        e.preventDefault();
+    };
+
+    return el('a.ms-3', { href: '#', onClick: toggle, title: path || "" },
+              path ? "STOP RECORDING" : "RECORD");
+}
+
+function Schedule(props) {
+    const [scheduled, setScheduled] = useState([]);
+    const [now, setNow] = useState(Date.now() / 1000);
+
+    const fetch_scheduled = () => {
+        fetch('/schedule')
+            .then((response) => response.json())
+            .then((data) => setScheduled(data));
+    };
+
+    const on_message = (message) => {
+        switch (message.event) {
+        case "Resync":
+            fetch_scheduled();
+            break;
+        case "Scheduled":
+            setScheduled((s) => s.concat([message.scheduled]));
+            break;
+        case "Done":
+            setScheduled((s) => s.filter((entry) => entry.id !== message.id));
+            break;
+        }
+    };
+
+    const bus = useContext(Bus);
+    useEffect(() => {
+        fetch_scheduled();
+        bus.on("schedule", on_message);
+        const timer = setInterval(() => setNow(Date.now() / 1000), 1000);
+        return () => { bus.off("schedule"); clearInterval(timer); };
+    }, []);
+
+    const cancel = (id) => (e) => {
+        cancel_scheduled_request(id);
+        e.preventDefault();
+    };
+
+    return e('ul.list-unstyled',
+             scheduled.map((entry) =>
+                 el('li', { key: `scheduled-${entry.id}` },
+                    `${action_name(entry.action)} in ${Math.max(0, Math.round(entry.at - now))}s `,
+                    el('a', { href: '#', onClick: cancel(entry.id) }, "cancel"))));
+}
+
+// serde writes unit variants as plain strings
+function action_name(action) {
+    return typeof action === 'string' ? action : Object.keys(action)[0];
 }
 
 function Collection(props) {
@@ -96,65 +261,136 @@ function Collection(props) {
         e.preventDefault();
     };
 
+    const [gain, setGain] = useState(1.0);
     const on_gain_change = (e) => {
-        let gain = e.target.valueAsNumber;
-        coll_playback_request(props.id, gain);
+        setGain(e.target.valueAsNumber);
+        coll_playback_request(props.id, e.target.valueAsNumber);
     };
 
+    const bus = useContext(Bus);
+    useEffect(() => {
+        let key = `${props.id}/gain`;
+        bus.on(key, setGain);
+        return () => bus.off(key);
+    }, []);
+
     let [collapsed, setCollapsed] = useState(true);
     const toggleCollapsed = () => {
         setCollapsed((c) => !c);
     };
-    
+
+    let [emitter, setEmitter] = useState(null);
+    const toggle_emitter = (e) => {
+        if (emitter === null) {
+            start_emitter_request(props.id)
+                .then((response) => response.json())
+                .then((data) => setEmitter(data.id));
+        } else {
+            stop_emitter_request(emitter);
+            setEmitter(null);
+        }
+        e.preventDefault();
+    };
 
     return el('div.d-grid.gap-3', { key: `coll-${props.id}` },
               e('div.row',
                 e('div.col',
                   el('span.fs-2.me-3', { onClick: toggleCollapsed }, props.name),
                   e('span.badge.rounded-pill.text-bg-primary.me-3', props.kind),
-                  el('span', { href: "#", onClick: play_random }, "Play Random"))),
-              e('div.row',
-                e('div.range', el('input.form-range', { type: 'range', min: 0.0, max: 1.5, step: 0.01, onChange: on_gain_change }))),
+                  el('span.me-3', { href: "#", onClick: play_random }, "Play Random"),
+                  props.can_emit &&
+                  el('span', { href: "#", onClick: toggle_emitter },
+                     emitter === null ? "Sprinkle" : "Stop Sprinkling"))),
+              props.gm && e('div.row',
+                e('div.range', el('input.form-range', { type: 'range', min: 0.0, max: 1.5, step: 0.01, value: gain, onChange: on_gain_change }))),
               chunks.map(chunk =>
                   el('div.row', {'class': collapsed?'collapse':''}, chunk.map(clip =>
                       e('div.col-md-4',
                         h(Clip, { coll_id: props.id,
                                   id: clip.id,
-                                  name: clip.name}))))));
+                                  name: clip.name,
+                                  layers: clip.layers,
+                                  gm: props.gm}))))));
 }
 
 const card_class_for_state = {
     "pending": "card bg-secondary text-light",
     "started": "card bg-success text-light",
+    "paused": "card bg-warning text-dark",
     "stopped": "card bg-light text-dark",
 };
 
 function Clip(props) {
     const play = (e) => {
-        setPlaying("pending");
-        play_clip_request(props.coll_id, props.id);
+        setPending(true);
+        play_clip_request(props.coll_id, props.id)
+            .then((response) => { if (!response.ok) setPending(false); });
         e.preventDefault();
     };
 
     const stop = (e) => {
-        setPlaying("pending");
+        setPending(true);
         stop_clip_request(props.coll_id, props.id);
         e.preventDefault();
     };
 
-    const [playingState, setPlaying] = useState("stopped");
+    const [intensity, setIntensity] = useState(0.0);
+    const on_intensity_change = (e) => {
+        setIntensity(e.target.valueAsNumber);
+        clip_intensity_request(props.coll_id, props.id, e.target.valueAsNumber);
+    };
+
+    // A clip can be playing several times over; it's playing as long
+    // as any of its instances are.
+    const [instances, setInstances] = useState([]);
+    // Those of the instances which are paused
+    const [paused, setPaused] = useState([]);
+    const [pending, setPending] = useState(false);
+    const playingState = pending ? "pending"
+          : instances.length === 0 ? "stopped"
+          : instances.every((id) => paused.includes(id)) ? "paused"
+          : "started";
 
     const on_message = (message) => {
         switch (message.event) {
         case "Started":
-            setPlaying("started");
+            setPending(false);
+            setInstances((ids) => ids.includes(message.instance_id) ? ids : [...ids, message.instance_id]);
             break;
         case "Stopped":
-            setPlaying("stopped");
+            setPending(false);
+            setInstances((ids) => ids.filter((id) => id !== message.instance_id));
+            setPaused((ids) => ids.filter((id) => id !== message.instance_id));
+            break;
+        case "Paused":
+            setPaused((ids) => ids.includes(message.instance_id) ? ids : [...ids, message.instance_id]);
+            break;
+        case "Resumed":
+            setPaused((ids) => ids.filter((id) => id !== message.instance_id));
+            break;
+        case "Error":
+            setPending(false);
+            break;
+        case "IntensityChanged":
+            setIntensity(message.intensity);
+            break;
+        case "Playing":
+            setPending(false);
+            setInstances(message.instance_ids);
+            setPaused(message.paused_ids);
+            setIntensity(message.intensity);
             break;
         }
     };
 
+    // Played again, a clip starts from its lowest intensity.
+    const stopped = instances.length === 0;
+    useEffect(() => {
+        if (stopped) {
+            setIntensity(0.0);
+        }
+    }, [stopped]);
+
     const bus = useContext(Bus);
     useEffect(() => {
         let key = `${props.coll_id}/${props.id}`;
@@ -168,13 +404,17 @@ function Clip(props) {
                       style: 'cursor: pointer; transition: all 0.2s ease-out;',
                       onClick: playingState !== "stopped" ? stop : play },
               e('div.card-body',
-                props.name));
+                props.name,
+                props.gm && props.layers > 1 && playingState === "started" &&
+                el('input.form-range', { type: 'range', min: 0.0, max: 1.0, step: 0.01, value: intensity,
+                                         onClick: (e) => e.stopPropagation(),
+                                         onChange: on_intensity_change })));
 }
 
 render(h(App), document.body);
 
 function play_clip_request(coll_id, clip_id) {
-    fetch(`/collection/${coll_id}/clip/${clip_id}/play`, { method: 'POST' });
+    return fetch(`/collection/${coll_id}/clip/${clip_id}/play`, { method: 'POST' });
 }
 
 function stop_clip_request(coll_id, clip_id) {
@@ -195,3 +435,27 @@ function coll_playback_request(coll_id, gain) {
             headers: { 'Content-Type': 'application/json' },
             body: JSON.stringify({ gain: gain }) });
 }
+
+function clip_intensity_request(coll_id, clip_id, intensity) {
+    fetch(`/collection/${coll_id}/clip/${clip_id}/intensity`,
+          { method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({ intensity: intensity }) });
+}
+
+function cancel_scheduled_request(id) {
+    fetch(`/schedule/${id}/cancel`, { method: 'POST' });
+}
+
+function start_emitter_request(coll_id) {
+    return fetch(`/collection/${coll_id}/emitter`,
+                 { method: 'POST',
+                   headers: { 'Content-Type': 'application/json' },
+                   body: JSON.stringify({ min_interval: 20, max_interval: 60,
+                                          min_gain: 0.6, max_gain: 1.0,
+                                          min_pan: -0.8, max_pan: 0.8 }) });
+}
+
+function stop_emitter_request(id) {
+    fetch(`/emitter/${id}/stop`, { method: 'POST' });
+}
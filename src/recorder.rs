@@ -0,0 +1,319 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use kira::dsp::Frame;
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+use thiserror::Error;
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    RwLock,
+};
+use tracing::{error, info};
+
+use crate::{events::Sequenced, model::Library, output::Sink, player::PlayerEvent};
+
+/// How much audio the recorder can fall behind by, in seconds, before
+/// frames are dropped.
+const BUFFER_SECONDS: usize = 2;
+
+/// The output which feeds the recorder.
+#[derive(Clone, Default)]
+pub struct Tap {
+    session: Arc<Mutex<Option<Session>>>,
+    // Frames that couldn't be recorded, this session
+    dropped_frames: Arc<AtomicU64>,
+}
+
+struct Session {
+    id: u64,
+    // The mix, for the writer thread
+    producer: HeapProducer<Frame>,
+    sender: mpsc::Sender<Message>,
+    // The file being written to
+    path: PathBuf,
+}
+
+impl Sink for Tap {
+    // If the recorder is busy, or the writer can't keep up, the frames
+    // are dropped.
+    fn write(&mut self, frames: &[Frame]) {
+        if let Ok(mut session) = self.session.try_lock() {
+            if let Some(session) = session.as_mut() {
+                let dropped = frames.len() - session.producer.push_slice(frames);
+                if dropped > 0 {
+                    self.dropped_frames
+                        .fetch_add(dropped as u64, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+enum Message {
+    Marker(String),
+    Stop,
+}
+
+#[derive(Clone, Debug)]
+pub struct RecorderSettings {
+    pub directory: PathBuf,
+    /// Start a new file after this long.
+    pub rotate_after: Option<Duration>,
+    /// Of the mix
+    pub sample_rate: u32,
+}
+
+/// Records the mix to WAV files, with an Audacity label track of what
+/// started and stopped alongside each one.
+pub struct Recorder {
+    tap: Tap,
+    settings: RecorderSettings,
+    next_session_id: AtomicU64,
+}
+
+impl Recorder {
+    pub fn new(settings: RecorderSettings) -> Recorder {
+        Recorder {
+            tap: Tap::default(),
+            settings,
+            next_session_id: AtomicU64::new(1),
+        }
+    }
+
+    pub fn tap(&self) -> Tap {
+        self.tap.clone()
+    }
+
+    /// Start recording. Returns the path of the first file.
+    pub fn start(&self) -> Result<PathBuf, RecorderError> {
+        let mut session = self.tap.session.lock().unwrap();
+        if session.is_some() {
+            return Err(RecorderError::AlreadyRecording);
+        }
+
+        let file = RecordingFile::create(&self.settings)?;
+        let path = file.path.clone();
+
+        let id = self.next_session_id.fetch_add(1, Ordering::SeqCst);
+        let buffer = HeapRb::new(self.settings.sample_rate as usize * BUFFER_SECONDS);
+        let (producer, consumer) = buffer.split();
+        let (sender, receiver) = mpsc::channel();
+        let tap = self.tap.clone();
+        let settings = self.settings.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = write_recording(id, file, &settings, &tap, consumer, receiver) {
+                error!(err = &e as &dyn std::error::Error, "Error recording");
+                // Give up on this session, unless it was stopped already.
+                let mut session = tap.session.lock().unwrap();
+                if session.as_ref().map_or(false, |session| session.id == id) {
+                    *session = None;
+                }
+            }
+        });
+
+        self.tap.dropped_frames.store(0, Ordering::Relaxed);
+        *session = Some(Session {
+            id,
+            producer,
+            sender,
+            path: path.clone(),
+        });
+        info!("Recording to {}", path.display());
+        Ok(path)
+    }
+
+    /// Stop recording. The file is finished off in the background.
+    pub fn stop(&self) -> Result<(), RecorderError> {
+        let session = self.tap.session.lock().unwrap().take();
+        match session {
+            Some(session) => {
+                let _ = session.sender.send(Message::Stop);
+                Ok(())
+            }
+            None => Err(RecorderError::NotRecording),
+        }
+    }
+
+    /// Add a label at the current point of the recording, if there is one.
+    pub fn mark(&self, label: String) {
+        if let Some(session) = self.tap.session.lock().unwrap().as_ref() {
+            let _ = session.sender.send(Message::Marker(label));
+        }
+    }
+
+    /// How many frames of the mix the recording has missed since it
+    /// started, because the disk couldn't keep up.
+    pub fn dropped_frames(&self) -> u64 {
+        self.tap.dropped_frames.load(Ordering::Relaxed)
+    }
+
+    /// The file being recorded to, if any.
+    pub fn recording(&self) -> Option<PathBuf> {
+        self.tap
+            .session
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|session| session.path.clone())
+    }
+}
+
+/// Mark each clip starting and stopping in the recording.
+pub async fn mark_events(
+    recorder: Arc<Recorder>,
+    library: Arc<RwLock<Library>>,
+    mut events: Receiver<Sequenced>,
+) {
+    loop {
+        let event = match events.recv().await {
+            Ok(sequenced) => sequenced.event,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return,
+        };
+        let (verb, coll_id, clip_id) = match event {
+            PlayerEvent::Started {
+                coll_id, clip_id, ..
+            } => ("Started", coll_id, clip_id),
+            PlayerEvent::Stopped {
+                coll_id, clip_id, ..
+            } => ("Stopped", coll_id, clip_id),
+            _ => continue,
+        };
+
+        let library = library.read().await;
+        let name = library
+            .collection(coll_id)
+            .and_then(|coll| coll.clip(clip_id))
+            .map(|clip| clip.name.as_str())
+            .unwrap_or("<unknown>");
+        recorder.mark(format!("{verb} {name}"));
+    }
+}
+
+struct RecordingFile {
+    path: PathBuf,
+    wav: hound::WavWriter<BufWriter<File>>,
+    labels: BufWriter<File>,
+    frames: u64,
+    sample_rate: u32,
+}
+
+impl RecordingFile {
+    fn create(settings: &RecorderSettings) -> Result<RecordingFile, RecorderError> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let path = settings.directory.join(format!("soundboard-{millis}.wav"));
+
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: settings.sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let wav = hound::WavWriter::create(&path, spec)?;
+        let labels = BufWriter::new(File::create(path.with_extension("txt"))?);
+
+        Ok(RecordingFile {
+            path,
+            wav,
+            labels,
+            frames: 0,
+            sample_rate: settings.sample_rate,
+        })
+    }
+
+    fn seconds(&self) -> f64 {
+        self.frames as f64 / self.sample_rate as f64
+    }
+
+    fn finish(mut self) -> Result<(), RecorderError> {
+        self.labels.flush()?;
+        self.wav.finalize()?;
+        Ok(())
+    }
+}
+
+fn write_recording(
+    session_id: u64,
+    mut file: RecordingFile,
+    settings: &RecorderSettings,
+    tap: &Tap,
+    mut frames: HeapConsumer<Frame>,
+    messages: mpsc::Receiver<Message>,
+) -> Result<(), RecorderError> {
+    let frames_per_file = settings
+        .rotate_after
+        .map(|rotate_after| (rotate_after.as_secs_f64() * settings.sample_rate as f64) as u64);
+
+    let mut stopped = false;
+    loop {
+        // Write out what's come in so far, so that a marker goes where
+        // it happened.
+        while let Some(frame) = frames.pop() {
+            file.wav.write_sample(frame.left)?;
+            file.wav.write_sample(frame.right)?;
+            file.frames += 1;
+
+            if frames_per_file.map_or(false, |max| file.frames >= max) {
+                let mut session = tap.session.lock().unwrap();
+                match session.as_mut() {
+                    Some(session) if session.id == session_id => {
+                        file.finish()?;
+                        file = RecordingFile::create(settings)?;
+                        info!("Recording to {}", file.path.display());
+                        session.path = file.path.clone();
+                    }
+                    // Stopped; just write out what's left.
+                    _ => (),
+                }
+            }
+        }
+
+        if stopped {
+            break;
+        }
+
+        match messages.recv_timeout(Duration::from_millis(50)) {
+            // Audacity's label format: start, end and text, tab separated
+            Ok(Message::Marker(label)) => {
+                let at = file.seconds();
+                writeln!(file.labels, "{at:.6}\t{at:.6}\t{label}")?;
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => (),
+            // A dropped sender is as good as a stop. Either way, write
+            // out whatever was still on its way first.
+            Ok(Message::Stop) | Err(mpsc::RecvTimeoutError::Disconnected) => stopped = true,
+        }
+    }
+
+    let dropped = tap.dropped_frames.load(Ordering::Relaxed);
+    if dropped > 0 {
+        info!("The recording missed {dropped} frames");
+    }
+    file.finish()
+}
+
+#[derive(Error, Debug)]
+pub enum RecorderError {
+    #[error("Already recording")]
+    AlreadyRecording,
+
+    #[error("Not recording")]
+    NotRecording,
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Wav(#[from] hound::Error),
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use tokio::{
+    sync::{Mutex, RwLock},
+    task::JoinHandle,
+};
+use tracing::error;
+
+use crate::{
+    events::EventBus,
+    model::Library,
+    player::{PlayOptions, Player, PlayerError, PlayerEvent, StopOptions},
+};
+
+/// Runs player actions at a later time. Each scheduled action is a
+/// tokio task sleeping until it's due; cancelling it aborts the task.
+pub struct Scheduler {
+    library: Arc<RwLock<Library>>,
+    player: Arc<Mutex<Player>>,
+    events: EventBus,
+    entries: Arc<Mutex<HashMap<u64, Entry>>>,
+    next_id: AtomicU64,
+}
+
+struct Entry {
+    action: Action,
+    at: SystemTime,
+    task: JoinHandle<()>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Action {
+    Play {
+        coll_id: u64,
+        clip_id: u64,
+    },
+    Stop {
+        coll_id: u64,
+        clip_id: u64,
+    },
+    StopCollection {
+        coll_id: u64,
+    },
+    StopAll,
+    Fade {
+        coll_id: u64,
+        gain: f64,
+        duration: Duration,
+    },
+}
+
+impl Scheduler {
+    pub fn new(
+        library: Arc<RwLock<Library>>,
+        player: Arc<Mutex<Player>>,
+        events: EventBus,
+    ) -> Scheduler {
+        Scheduler {
+            library,
+            player,
+            events,
+            entries: Default::default(),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Run `action` at `at`, or right away if that's in the past.
+    /// Returns the id of the scheduled action.
+    pub async fn schedule(&self, action: Action, at: SystemTime) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+
+        // Hold the lock until the entry is in place, so that even an
+        // action that's due immediately finds itself there.
+        let mut entries = self.entries.lock().await;
+
+        let task = {
+            let action = action.clone();
+            let library = self.library.clone();
+            let player = self.player.clone();
+            let events = self.events.clone();
+            let entries = self.entries.clone();
+            tokio::spawn(async move {
+                let delay = at
+                    .duration_since(SystemTime::now())
+                    .unwrap_or(Duration::ZERO);
+                tokio::time::sleep(delay).await;
+
+                if entries.lock().await.remove(&id).is_none() {
+                    return;
+                }
+
+                let result = run_action(&action, &library, &player).await;
+                if let Err(e) = &result {
+                    error!(
+                        err = e as &dyn std::error::Error,
+                        "Error running scheduled action"
+                    );
+                }
+
+                events.send(PlayerEvent::Fired {
+                    id,
+                    error: result.err().map(|e| e.to_string()),
+                });
+            })
+        };
+
+        entries.insert(
+            id,
+            Entry {
+                action: action.clone(),
+                at,
+                task,
+            },
+        );
+
+        self.events.send(PlayerEvent::Scheduled { id, at, action });
+
+        id
+    }
+
+    /// Cancel a scheduled action. Returns false if there was no such
+    /// action, or it has already run.
+    pub async fn cancel(&self, id: u64) -> bool {
+        let entry = self.entries.lock().await.remove(&id);
+        match entry {
+            Some(entry) => {
+                entry.task.abort();
+                self.events.send(PlayerEvent::Cancelled { id });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// All pending actions, soonest first.
+    pub async fn scheduled(&self) -> Vec<(u64, SystemTime, Action)> {
+        let entries = self.entries.lock().await;
+        let mut scheduled = entries
+            .iter()
+            .map(|(id, entry)| (*id, entry.at, entry.action.clone()))
+            .collect::<Vec<_>>();
+        scheduled.sort_by_key(|(id, at, _)| (*at, *id));
+        scheduled
+    }
+}
+
+async fn run_action(
+    action: &Action,
+    library: &RwLock<Library>,
+    player_mutex: &Mutex<Player>,
+) -> Result<(), PlayerError> {
+    let library = library.read().await;
+    let mut player = player_mutex.lock().await;
+    match *action {
+        Action::Play { coll_id, clip_id } => {
+            let coll = library.collection(coll_id).ok_or(PlayerError::NotFound)?;
+            let clip = coll.clip(clip_id).ok_or(PlayerError::NotFound)?;
+            player
+                .play_clip(coll, clip, PlayOptions::default())
+                .map(|_| ())
+        }
+        Action::Stop { coll_id, clip_id } => {
+            player.stop_clip(coll_id, clip_id, &StopOptions::default())
+        }
+        Action::StopCollection { coll_id } => player.stop_coll(coll_id, &StopOptions::default()),
+        Action::StopAll => player.stop_all(&StopOptions::default()),
+        Action::Fade {
+            coll_id,
+            gain,
+            duration,
+        } => player.fade_gain(coll_id, gain, duration),
+    }
+}
@@ -1,31 +1,70 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use axum::{
-    extract::Path,
-    http::StatusCode,
-    response::sse::{Event, KeepAlive, Sse},
+    body::{Bytes, StreamBody},
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     routing::{get, post},
-    Extension, Json, Router,
+    Extension, Router,
 };
+use axum_server::tls_rustls::RustlsConfig;
 use axum_static_macro::static_file;
 use futures::{stream::Stream, StreamExt};
-use tokio::sync::{broadcast::Sender, Mutex};
+use kira::sound::FromFileError;
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt, SeekFrom},
+    sync::{Mutex, RwLock},
+};
 use tracing::{error, info};
+use utoipa::OpenApi;
 
 use crate::{
     api,
-    model::Library,
-    player::{Player, PlayerEvent},
+    auth::{self, Access, Auth},
+    emitter::Emitters,
+    events::{EventBus, Sequenced, Update},
+    extract::{Json, Path, Query},
+    model::{self, CollectionKind, Library},
+    output::OutputControls,
+    player::{self, BusId, PlayOptions, Player, PlayerError, PlayerEvent, StopOptions},
+    recorder::{Recorder, RecorderError},
+    scheduler::{self, Scheduler},
+    stream::LiveStream,
+    tls::TlsFiles,
+    ws,
 };
 
+/// Everything the handlers need.
+pub struct Services {
+    pub library: Arc<RwLock<Library>>,
+    pub player: Arc<Mutex<Player>>,
+    pub event_bus: EventBus,
+    pub scheduler: Arc<Scheduler>,
+    pub emitters: Arc<Emitters>,
+    pub recorder: Arc<Recorder>,
+    pub output_controls: OutputControls,
+    pub stream: Option<LiveStream>,
+    /// Without it, anyone who can reach the server can do anything.
+    pub auth: Option<Arc<Auth>>,
+}
+
 pub async fn run_server(
     address: SocketAddr,
-    library: Arc<Library>,
-    player: Arc<Mutex<Player>>,
-    player_event_broadcast: Sender<PlayerEvent>,
+    tls: Option<TlsFiles>,
+    services: Services,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     static_file!(index_html, "public/index.html", "text/html");
     static_file!(index_js, "public/index.js", "application/javascript");
+    static_file!(login_html, "public/login.html", "text/html");
     static_file!(
         preact_preact_mjs,
         "public/preact/dist/preact.mjs",
@@ -47,153 +86,1330 @@ pub async fn run_server(
         "application/javascript"
     );
 
-    // build our application with a single route
-    let app = Router::new()
-        // .route("/", get(root))
-        .route("/", get(index_html))
-        .route("/index.js", get(index_js))
+    let api = Router::new()
+        .route("/session", get(session))
         .route("/collection", get(collections))
+        .route("/library/rescan", post(rescan_library))
         .route("/playing", get(playing))
+        .route("/state", get(state))
         .route("/collection/:coll_id/playback", post(coll_playback))
         .route("/collection/:coll_id/stop", post(stop_coll))
         .route("/collection/:coll_id/clip/:clip_id/play", post(play_clip))
+        .route("/collection/:coll_id/clip/:clip_id/audio", get(clip_audio))
         .route("/collection/:coll_id/clip/:clip_id/stop", post(stop_clip))
+        .route(
+            "/collection/:coll_id/clip/:clip_id/intensity",
+            post(clip_intensity),
+        )
+        .route(
+            "/collection/:coll_id/clip/:clip_id/playback",
+            post(clip_playback),
+        )
+        .route("/collection/:coll_id/effect/:index", post(coll_effect))
+        .route("/bus/:kind/effect/:index", post(kind_effect))
+        .route("/instance/:instance_id/stop", post(stop_instance))
         .route("/stop_all", post(stop_all))
+        .route("/schedule", get(scheduled).post(schedule))
+        .route("/schedule/:id/cancel", post(cancel_scheduled))
+        .route("/collection/:coll_id/emitter", post(start_emitter))
+        .route("/emitter", get(emitters))
+        .route("/emitter/:id/stop", post(stop_emitter))
+        .route("/recording", get(recording))
+        .route("/recording/start", post(start_recording))
+        .route("/recording/stop", post(stop_recording))
+        .route("/output", get(outputs))
+        .route("/output/:name/gain", post(output_gain))
+        .route("/stream.mp3", get(stream))
         .route("/events", get(events))
+        .route("/ws", get(ws::ws))
+        .route("/openapi.json", get(openapi));
+
+    let app = Router::new()
+        .route("/", get(index_html))
+        .route("/index.js", get(index_js))
+        .route("/login", get(login_html).post(auth::login))
+        .route("/logout", post(auth::logout))
         .route("/preact/preact.mjs", get(preact_preact_mjs))
         .route("/preact/hooks.mjs", get(preact_hooks_mjs))
         .route("/preact/debug.mjs", get(preact_debug_mjs))
         .route("/preact/devtools.mjs", get(preact_devtools_mjs))
-        .layer(Extension(library))
-        .layer(Extension(player))
-        .layer(Extension(player_event_broadcast));
+        .nest("/api/v1", api.clone())
+        // The same routes, from before there was a /api/v1
+        .merge(api)
+        // Needs the extensions below, so goes inside them.
+        .layer(middleware::from_fn(auth::require_login))
+        .layer(Extension(services.library))
+        .layer(Extension(services.player))
+        .layer(Extension(services.event_bus))
+        .layer(Extension(services.scheduler))
+        .layer(Extension(services.emitters))
+        .layer(Extension(services.recorder))
+        .layer(Extension(services.output_controls))
+        .layer(Extension(services.stream))
+        .layer(Extension(services.auth));
+
+    match tls {
+        Some(tls) => {
+            let config = RustlsConfig::from_pem_file(&tls.cert, &tls.key).await?;
+            info!("Running https server on https://{address}");
 
-    info!("Running http server on http://{address}");
+            Ok(axum_server::bind_rustls(address, config)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await?)
+        }
+        None => {
+            info!("Running http server on http://{address}");
+
+            Ok(axum::Server::bind(&address)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await?)
+        }
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        session,
+        collections,
+        rescan_library,
+        playing,
+        state,
+        play_clip,
+        clip_audio,
+        stop_clip,
+        stop_instance,
+        stop_coll,
+        stop_all,
+        coll_playback,
+        clip_intensity,
+        scheduled,
+        schedule,
+        cancel_scheduled,
+        emitters,
+        start_emitter,
+        stop_emitter,
+        clip_playback,
+        coll_effect,
+        kind_effect,
+        recording,
+        start_recording,
+        stop_recording,
+        outputs,
+        output_gain,
+        stream,
+        events
+    ),
+    components(schemas(
+        api::Session,
+        api::Role,
+        api::Collection,
+        api::Clip,
+        api::Error,
+        api::Status,
+        api::Instance,
+        api::Output,
+        api::State,
+        api::InstanceState,
+        api::CollectionState,
+        api::BusState,
+        api::Recording,
+        api::PlaybackParams,
+        api::IntensityParams,
+        api::PlayParams,
+        api::StopParams,
+        api::ClipPlaybackParams,
+        api::EffectParams,
+        api::ScheduleParams,
+        api::Scheduled,
+        api::ScheduledAction,
+        api::EmitterParams,
+        api::Emitter,
+        model::CollectionKind,
+        model::FilterMode,
+        model::Position,
+        player::Quantize,
+        player::FadeCurve,
+    ))
+)]
+struct ApiDoc;
 
-    Ok(axum::Server::bind(&address)
-        .serve(app.into_make_service())
-        .await?)
+async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
 }
 
-async fn collections(Extension(library): Extension<Arc<Library>>) -> Json<Vec<api::Collection>> {
-    let api_lib: api::Library = (*library).clone().into();
+#[utoipa::path(
+    get,
+    path = "/api/v1/collection",
+    responses(
+        (status = 200, description = "Every collection and its clips", body = [api::Collection]),
+    )
+)]
+async fn collections(
+    Extension(library): Extension<Arc<RwLock<Library>>>,
+    Extension(access): Extension<Access>,
+) -> Json<Vec<api::Collection>> {
+    let mut library = library.read().await.clone();
+    // Players only see what they can play.
+    library
+        .collections
+        .retain(|coll| access.may_control(coll.id));
+    let api_lib: api::Library = library.into();
 
     Json(api_lib.collections)
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/session",
+    responses(
+        (status = 200, description = "Who the client is logged in as", body = api::Session),
+        (status = 401, description = "Not logged in", body = api::Error),
+    )
+)]
+async fn session(Extension(access): Extension<Access>) -> Json<api::Session> {
+    Json(api::Session {
+        role: access.role().into(),
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/library/rescan",
+    responses(
+        (status = 200, description = "Every collection and its clips, as they are now", body = [api::Collection]),
+    )
+)]
+async fn rescan_library(
+    Extension(library): Extension<Arc<RwLock<Library>>>,
+    Extension(player_mutex): Extension<Arc<Mutex<Player>>>,
+    Extension(event_bus): Extension<EventBus>,
+) -> Result<Json<Vec<api::Collection>>, ApiError> {
+    info!("Rescan library");
+    // Nobody has to wait on the directories being read.
+    let current = library.read().await.clone();
+    let rescan = {
+        let current = current.clone();
+        tokio::task::spawn_blocking(move || current.rescan())
+    };
+    let (rescanned, errors) = rescan.await.map_err(|e| ApiError::internal(&e))?;
+    for (directory, e) in errors.iter() {
+        error!(err = %e as &dyn std::error::Error, "Couldn't rescan {}", directory.display());
+    }
+
+    *library.write().await = rescanned.clone();
+
+    // New effects need new buses.
+    let mut player = player_mutex.lock().await;
+    for coll in rescanned.collections.iter() {
+        let old_effects = current.collection(coll.id).map(|coll| &coll.effects);
+        if old_effects != Some(&coll.effects) {
+            player.replace_collection_bus(coll)?;
+        }
+    }
+    drop(player);
+
+    event_bus.send(PlayerEvent::LibraryChanged);
+
+    let api_lib: api::Library = rescanned.into();
+    Ok(Json(api_lib.collections))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/playing",
+    responses(
+        (status = 200, description = "[coll_id, clip_id, instance_id] for each playing instance"),
+    )
+)]
 async fn playing(
     Extension(player_mutex): Extension<Arc<Mutex<Player>>>,
-) -> Json<Vec<(String, String)>> {
+    Extension(access): Extension<Access>,
+) -> Json<Vec<(String, String, String)>> {
     let playing = {
         let player = player_mutex.lock().await;
         player
-            .playing_clips()
+            .playing_instances()
             .into_iter()
-            .map(|(coll_id, clip_id)| (coll_id.to_string(), clip_id.to_string()))
+            .filter(|(_, coll_id, _)| access.may_control(*coll_id))
+            .map(|(instance_id, coll_id, clip_id)| {
+                (
+                    coll_id.to_string(),
+                    clip_id.to_string(),
+                    instance_id.to_string(),
+                )
+            })
             .collect::<Vec<_>>()
     };
 
     Json(playing)
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/state",
+    responses(
+        (status = 200, description = "Everything the player is doing", body = api::State),
+    )
+)]
+async fn state(
+    Extension(player_mutex): Extension<Arc<Mutex<Player>>>,
+    Extension(event_bus): Extension<EventBus>,
+    Extension(output_controls): Extension<OutputControls>,
+    Extension(access): Extension<Access>,
+) -> Result<Json<api::State>, ApiError> {
+    Ok(Json(
+        current_state(&player_mutex, &event_bus, &output_controls, &access).await?,
+    ))
+}
+
+async fn current_state(
+    player_mutex: &Mutex<Player>,
+    event_bus: &EventBus,
+    output_controls: &OutputControls,
+    access: &Access,
+) -> Result<api::State, PlayerError> {
+    let (seq, mut snapshot) = {
+        let mut player = player_mutex.lock().await;
+        // Send out whatever the player's holding on to first, so no
+        // event after `seq` is already part of the snapshot.
+        player.publish_events(event_bus)?;
+        (event_bus.last_seq(), player.snapshot())
+    };
+
+    // Players only see their own collections.
+    snapshot
+        .instances
+        .retain(|instance| access.may_control(instance.coll_id));
+    snapshot
+        .coll_gains
+        .retain(|(coll_id, _)| access.may_control(*coll_id));
+    snapshot.buses.retain(|(bus_id, _)| match bus_id {
+        BusId::Collection(coll_id) => access.may_control(*coll_id),
+        BusId::Kind(_) => matches!(access, Access::Gm),
+    });
+    let outputs = match access {
+        Access::Gm => output_controls.list(),
+        Access::Player(_) => vec![],
+    };
+
+    Ok(api::State::new(seq, snapshot, outputs))
+}
+
+/// The events a client may see, from now on or from after
+/// `resume_from`, and its state over again whenever it falls behind.
+/// For /events and the websocket.
+pub fn client_updates(
+    resume_from: Option<u64>,
+    player_mutex: Arc<Mutex<Player>>,
+    event_bus: EventBus,
+    output_controls: OutputControls,
+    access: Access,
+) -> impl Stream<Item = Update<api::State>> {
+    let visible = access.clone();
+    event_bus.clone().updates(
+        resume_from,
+        move |event| visible.may_see(event),
+        move || {
+            let player_mutex = player_mutex.clone();
+            let event_bus = event_bus.clone();
+            let output_controls = output_controls.clone();
+            let access = access.clone();
+            async move {
+                let state = current_state(&player_mutex, &event_bus, &output_controls, &access);
+                match state.await {
+                    Ok(state) => Some((state.seq, state)),
+                    Err(e) => {
+                        error!(err = %&e as &dyn std::error::Error, "Error resyncing events");
+                        None
+                    }
+                }
+            }
+        },
+    )
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/collection/{coll_id}/clip/{clip_id}/play",
+    params(
+        ("coll_id" = u64, Path, description = "Collection id"),
+        ("clip_id" = u64, Path, description = "Clip id"),
+        api::PlayQuery,
+    ),
+    request_body(content = api::PlayParams, description = "Optional"),
+    responses(
+        (status = 200, description = "The instance started", body = api::Instance),
+        (status = 400, description = "Invalid parameters", body = api::Error),
+        (status = 404, description = "No such collection or clip", body = api::Error),
+        (status = 409, description = "Too many instances already playing", body = api::Error),
+        (status = 415, description = "Unsupported audio format", body = api::Error),
+        (status = 422, description = "The audio couldn't be decoded", body = api::Error),
+    )
+)]
 async fn play_clip(
     Path((coll_id, clip_id)): Path<(u64, u64)>,
-    Extension(library): Extension<Arc<Library>>,
+    Query(query): Query<api::PlayQuery>,
+    body: Result<axum::Json<api::PlayParams>, JsonRejection>,
+    Extension(library): Extension<Arc<RwLock<Library>>>,
     Extension(player_mutex): Extension<Arc<Mutex<Player>>>,
-) -> Result<String, StatusCode> {
+) -> Result<Json<api::Instance>, ApiError> {
     info!("Play clip {coll_id}/{clip_id}");
 
+    let library = library.read().await;
     let mut player = player_mutex.lock().await;
-    let coll = library.collection(coll_id).ok_or(StatusCode::NOT_FOUND)?;
-    let clip = coll.clip(clip_id).ok_or(StatusCode::NOT_FOUND)?;
+    let coll = library.collection(coll_id).ok_or(PlayerError::NotFound)?;
+    let clip = coll.clip(clip_id).ok_or(PlayerError::NotFound)?;
 
-    player
-        .play_clip(coll_id, clip_id, clip.path.to_owned(), coll.kind)
-        .map_err(|e| {
-            error!(err = %&e as &dyn std::error::Error, "Error playing clip");
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    let placement = query.placement();
+    if !placement.map_or(true, |p| p.is_valid()) {
+        return Err(ApiError::bad_request("Invalid pan or position"));
+    }
+    let options = PlayOptions {
+        quantize: query.quantize,
+        placement,
+        ..Default::default()
+    };
+    let options = optional_body(body)?
+        .into_options(options)
+        .ok_or_else(|| ApiError::bad_request("Invalid play options"))?;
+    let instance = player.play_clip(coll, clip, options)?;
 
-    Ok("Playing".to_string())
+    Ok(Json(instance.into()))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/collection/{coll_id}/clip/{clip_id}/audio",
+    params(
+        ("coll_id" = u64, Path, description = "Collection id"),
+        ("clip_id" = u64, Path, description = "Clip id"),
+        ("Range" = Option<String>, Header, description = "A single range of bytes"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "The clip's audio file. For a layered clip, its first layer's.",
+            content_type = "audio/*"
+        ),
+        (status = 206, description = "Part of that file", content_type = "audio/*"),
+        (status = 404, description = "No such collection or clip", body = api::Error),
+        (status = 416, description = "The range is outside the file", body = api::Error),
+    )
+)]
+async fn clip_audio(
+    Path((coll_id, clip_id)): Path<(u64, u64)>,
+    headers: HeaderMap,
+    Extension(library): Extension<Arc<RwLock<Library>>>,
+    Extension(access): Extension<Access>,
+) -> Result<Response, ApiError> {
+    if !access.may_control(coll_id) {
+        return Err(ApiError::forbidden("Not allowed for players"));
+    }
+
+    // Only files in the library can be reached this way. A layered clip
+    // is previewed by its base layer.
+    let path = library
+        .read()
+        .await
+        .collection(coll_id)
+        .and_then(|coll| coll.clip(clip_id))
+        .and_then(|clip| clip.layers.first().cloned())
+        .ok_or(PlayerError::NotFound)?;
+
+    let mut file = tokio::fs::File::open(&path)
+        .await
+        .map_err(|e| ApiError::internal(&e))?;
+    let len = file
+        .metadata()
+        .await
+        .map_err(|e| ApiError::internal(&e))?
+        .len();
+
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|range| range.to_str().ok())
+        .map(|range| ByteRange::parse(range, len))
+        .unwrap_or(ByteRange::Whole);
+    let (status, start, end) = match range {
+        ByteRange::Whole => (StatusCode::OK, 0, len),
+        ByteRange::Part(start, end) => (StatusCode::PARTIAL_CONTENT, start, end),
+        ByteRange::Unsatisfiable => {
+            return Ok((
+                [(header::CONTENT_RANGE, format!("bytes */{len}"))],
+                ApiError::new(
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    "range_not_satisfiable",
+                    "The range is outside the file",
+                ),
+            )
+                .into_response())
+        }
+    };
+
+    file.seek(SeekFrom::Start(start))
+        .await
+        .map_err(|e| ApiError::internal(&e))?;
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(audio_mime_type(&path)),
+    );
+    response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(end - start));
+    if status == StatusCode::PARTIAL_CONTENT {
+        // SAFETY: this is all ascii
+        let content_range =
+            HeaderValue::from_str(&format!("bytes {start}-{}/{len}", end - 1)).unwrap();
+        response_headers.insert(header::CONTENT_RANGE, content_range);
+    }
+
+    Ok((
+        status,
+        response_headers,
+        StreamBody::new(read_chunks(file, end - start)),
+    )
+        .into_response())
+}
+
+/// What part of a file a request's Range header asks for. Ranges are
+/// end exclusive.
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    Whole,
+    Part(u64, u64),
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    // Only a single range is supported. For anything else the whole file
+    // is sent, which is allowed.
+    fn parse(range: &str, len: u64) -> ByteRange {
+        let (start, end) = match range
+            .strip_prefix("bytes=")
+            .filter(|spec| !spec.contains(','))
+            .and_then(|spec| spec.trim().split_once('-'))
+        {
+            Some(bounds) => bounds,
+            None => return ByteRange::Whole,
+        };
+
+        let bounds = match (start, end) {
+            // The last so many bytes
+            ("", suffix) => suffix
+                .parse::<u64>()
+                .ok()
+                .map(|suffix| (len.saturating_sub(suffix), len)),
+            (start, "") => start.parse().ok().map(|start| (start, len)),
+            (start, last) => match (start.parse::<u64>(), last.parse::<u64>()) {
+                (Ok(start), Ok(last)) if start <= last => {
+                    Some((start, last.saturating_add(1).min(len)))
+                }
+                // Backwards, so not a range at all
+                _ => None,
+            },
+        };
+
+        match bounds {
+            None => ByteRange::Whole,
+            Some((start, end)) if start >= end => ByteRange::Unsatisfiable,
+            Some((start, end)) => ByteRange::Part(start, end),
+        }
+    }
+}
+
+fn audio_mime_type(path: &std::path::Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("mp3") => "audio/mpeg",
+        Some("ogg" | "oga" | "opus") => "audio/ogg",
+        Some("flac") => "audio/flac",
+        Some("wav") => "audio/wav",
+        Some("m4a" | "mp4" | "aac") => "audio/mp4",
+        Some("webm") => "audio/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+// `len` bytes of `file`, from where it is now, a piece at a time.
+fn read_chunks(
+    mut file: tokio::fs::File,
+    mut len: u64,
+) -> impl Stream<Item = std::io::Result<Bytes>> {
+    async_stream::try_stream! {
+        let mut buf = vec![0; 64 * 1024];
+        while len > 0 {
+            let want = len.min(buf.len() as u64) as usize;
+            let read = file.read(&mut buf[..want]).await?;
+            // The file has got shorter since we looked.
+            if read == 0 {
+                break;
+            }
+            len -= read as u64;
+            yield Bytes::copy_from_slice(&buf[..read]);
+        }
+    }
+}
+
+// For endpoints whose body may be left out: no body at all means the
+// defaults, but one that isn't valid JSON is still an error.
+fn optional_body<T: Default>(body: Result<axum::Json<T>, JsonRejection>) -> Result<T, ApiError> {
+    match body {
+        Ok(axum::Json(body)) => Ok(body),
+        Err(JsonRejection::MissingJsonContentType(_)) => Ok(T::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Every gain a client asks for is checked against the same range.
+pub fn check_gain(gain: f64) -> Result<f64, ApiError> {
+    if api::GAINS.contains(&gain) {
+        Ok(gain)
+    } else {
+        Err(ApiError::bad_request("Invalid gain"))
+    }
+}
+
+// The stop endpoints all take an optional body saying how to fade out.
+fn stop_options(
+    body: Result<axum::Json<api::StopParams>, JsonRejection>,
+) -> Result<StopOptions, ApiError> {
+    optional_body(body)?
+        .into_options()
+        .ok_or_else(|| ApiError::bad_request("Invalid fade"))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/collection/{coll_id}/clip/{clip_id}/stop",
+    params(
+        ("coll_id" = u64, Path, description = "Collection id"),
+        ("clip_id" = u64, Path, description = "Clip id"),
+    ),
+    request_body(content = api::StopParams, description = "Optional"),
+    responses(
+        (status = 200, description = "Done", body = api::Status),
+        (status = 400, description = "Invalid parameters", body = api::Error),
+    )
+)]
 async fn stop_clip(
     Path((coll_id, clip_id)): Path<(u64, u64)>,
+    body: Result<axum::Json<api::StopParams>, JsonRejection>,
     Extension(player_mutex): Extension<Arc<Mutex<Player>>>,
-) -> Result<String, StatusCode> {
+) -> Result<Json<api::Status>, ApiError> {
     info!("Stop clip {coll_id}/{clip_id}");
+    let options = stop_options(body)?;
     let mut player = player_mutex.lock().await;
+    player.stop_clip(coll_id, clip_id, &options)?;
+
+    Ok(Json(api::Status::new("stopped")))
+}
 
-    player.stop_clip(coll_id, clip_id).map_err(|e| {
-        error!(err = %&e as &dyn std::error::Error, "Error stopping clip");
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+#[utoipa::path(
+    post,
+    path = "/api/v1/instance/{instance_id}/stop",
+    params(
+        ("instance_id" = u64, Path, description = "Instance id"),
+    ),
+    request_body(content = api::StopParams, description = "Optional"),
+    responses(
+        (status = 200, description = "Done", body = api::Status),
+        (status = 400, description = "Invalid parameters", body = api::Error),
+        (status = 404, description = "No such instance playing", body = api::Error),
+    )
+)]
+async fn stop_instance(
+    Path(instance_id): Path<u64>,
+    body: Result<axum::Json<api::StopParams>, JsonRejection>,
+    Extension(player_mutex): Extension<Arc<Mutex<Player>>>,
+) -> Result<Json<api::Status>, ApiError> {
+    info!("Stop instance {instance_id}");
+    let options = stop_options(body)?;
+    let mut player = player_mutex.lock().await;
+    player.stop_instance(instance_id, &options)?;
 
-    Ok("Stopped".to_string())
+    Ok(Json(api::Status::new("stopped")))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/v1/collection/{coll_id}/stop",
+    params(
+        ("coll_id" = u64, Path, description = "Collection id"),
+    ),
+    request_body(content = api::StopParams, description = "Optional"),
+    responses(
+        (status = 200, description = "Done", body = api::Status),
+        (status = 400, description = "Invalid parameters", body = api::Error),
+    )
+)]
 async fn stop_coll(
     Path(coll_id): Path<u64>,
+    body: Result<axum::Json<api::StopParams>, JsonRejection>,
     Extension(player_mutex): Extension<Arc<Mutex<Player>>>,
-) -> Result<String, StatusCode> {
+) -> Result<Json<api::Status>, ApiError> {
     info!("Stop collection {coll_id}");
+    let options = stop_options(body)?;
     let mut player = player_mutex.lock().await;
+    player.stop_coll(coll_id, &options)?;
 
-    player.stop_coll(coll_id).map_err(|e| {
-        error!(err = %&e as &dyn std::error::Error, "Error stopping collection");
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    Ok("Stopped".to_string())
+    Ok(Json(api::Status::new("stopped")))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/v1/stop_all",
+    request_body(content = api::StopParams, description = "Optional"),
+    responses(
+        (status = 200, description = "Done", body = api::Status),
+        (status = 400, description = "Invalid parameters", body = api::Error),
+    )
+)]
 async fn stop_all(
+    body: Result<axum::Json<api::StopParams>, JsonRejection>,
     Extension(player_mutex): Extension<Arc<Mutex<Player>>>,
-) -> Result<String, StatusCode> {
+) -> Result<Json<api::Status>, ApiError> {
     info!("Stop all");
+    let options = stop_options(body)?;
     let mut player = player_mutex.lock().await;
-    player.stop_all().map_err(|e| {
-        error!(err = %&e as &dyn std::error::Error, "Error stopping clips");
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    player.stop_all(&options)?;
 
-    Ok("Stopped".to_string())
+    Ok(Json(api::Status::new("stopped")))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/v1/collection/{coll_id}/playback",
+    params(
+        ("coll_id" = u64, Path, description = "Collection id"),
+    ),
+    request_body = api::PlaybackParams,
+    responses(
+        (status = 200, description = "Done", body = api::Status),
+        (status = 400, description = "Invalid parameters", body = api::Error),
+    )
+)]
 async fn coll_playback(
     Path(coll_id): Path<u64>,
     Json(body): Json<api::PlaybackParams>,
     Extension(player_mutex): Extension<Arc<Mutex<Player>>>,
-) -> Result<String, StatusCode> {
+) -> Result<Json<api::Status>, ApiError> {
     info!("Set collection playback params {coll_id}");
     let mut player = player_mutex.lock().await;
-    player.set_gain(coll_id, body.gain).map_err(|e| {
-        error!(err = %&e as &dyn std::error::Error, "Error stopping collection");
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    player.set_gain(coll_id, check_gain(body.gain)?)?;
 
-    Ok("".to_string())
+    Ok(Json(api::Status::new("ok")))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/v1/collection/{coll_id}/clip/{clip_id}/intensity",
+    params(
+        ("coll_id" = u64, Path, description = "Collection id"),
+        ("clip_id" = u64, Path, description = "Clip id"),
+    ),
+    request_body = api::IntensityParams,
+    responses(
+        (status = 200, description = "Done", body = api::Status),
+        (status = 404, description = "No such clip playing", body = api::Error),
+    )
+)]
+async fn clip_intensity(
+    Path((coll_id, clip_id)): Path<(u64, u64)>,
+    Json(body): Json<api::IntensityParams>,
+    Extension(player_mutex): Extension<Arc<Mutex<Player>>>,
+) -> Result<Json<api::Status>, ApiError> {
+    info!("Set clip intensity {coll_id}/{clip_id}");
+    let mut player = player_mutex.lock().await;
+    player.set_intensity(coll_id, clip_id, body.intensity)?;
+
+    Ok(Json(api::Status::new("ok")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/schedule",
+    responses(
+        (status = 200, description = "Pending scheduled actions", body = [api::Scheduled]),
+    )
+)]
+async fn scheduled(Extension(scheduler): Extension<Arc<Scheduler>>) -> Json<Vec<api::Scheduled>> {
+    let scheduled = scheduler
+        .scheduled()
+        .await
+        .into_iter()
+        .map(|(id, at, action)| api::Scheduled::new(id, at, action))
+        .collect();
+
+    Json(scheduled)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/schedule",
+    request_body = api::ScheduleParams,
+    responses(
+        (status = 200, description = "The scheduled action", body = api::Scheduled),
+        (status = 400, description = "Invalid parameters", body = api::Error),
+        (status = 404, description = "No such collection or clip", body = api::Error),
+    )
+)]
+async fn schedule(
+    Json(body): Json<api::ScheduleParams>,
+    Extension(library): Extension<Arc<RwLock<Library>>>,
+    Extension(scheduler): Extension<Arc<Scheduler>>,
+) -> Result<Json<api::Scheduled>, ApiError> {
+    let at = match (body.at, body.delay) {
+        (Some(at), None) => Duration::try_from_secs_f64(at)
+            .ok()
+            .and_then(|at| UNIX_EPOCH.checked_add(at)),
+        (None, Some(delay)) => Duration::try_from_secs_f64(delay)
+            .ok()
+            .and_then(|delay| SystemTime::now().checked_add(delay)),
+        _ => return Err(ApiError::bad_request("Give one of at or delay")),
+    }
+    .ok_or_else(|| ApiError::bad_request("Invalid time"))?;
+
+    let action = scheduler::Action::try_from(body.action)
+        .map_err(|e| ApiError::bad_request("Invalid action").with_details(e))?;
+    if let scheduler::Action::Play { coll_id, clip_id } = action {
+        library
+            .read()
+            .await
+            .collection(coll_id)
+            .and_then(|coll| coll.clip(clip_id))
+            .ok_or(PlayerError::NotFound)?;
+    }
+
+    info!("Schedule {action:?}");
+    let id = scheduler.schedule(action.clone(), at).await;
+
+    Ok(Json(api::Scheduled::new(id, at, action)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/schedule/{id}/cancel",
+    params(
+        ("id" = u64, Path, description = "Scheduled action id"),
+    ),
+    responses(
+        (status = 200, description = "Done", body = api::Status),
+        (status = 404, description = "No such scheduled action", body = api::Error),
+    )
+)]
+async fn cancel_scheduled(
+    Path(id): Path<u64>,
+    Extension(scheduler): Extension<Arc<Scheduler>>,
+) -> Result<Json<api::Status>, ApiError> {
+    info!("Cancel scheduled action {id}");
+    if !scheduler.cancel(id).await {
+        return Err(ApiError::not_found("No such scheduled action"));
+    }
+
+    Ok(Json(api::Status::new("cancelled")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/emitter",
+    responses(
+        (status = 200, description = "Running emitters", body = [api::Emitter]),
+    )
+)]
+async fn emitters(Extension(emitters): Extension<Arc<Emitters>>) -> Json<Vec<api::Emitter>> {
+    let running = emitters
+        .running()
+        .await
+        .into_iter()
+        .map(|(id, settings)| api::Emitter::new(id, settings))
+        .collect();
+
+    Json(running)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/collection/{coll_id}/emitter",
+    params(
+        ("coll_id" = u64, Path, description = "Collection id"),
+    ),
+    request_body = api::EmitterParams,
+    responses(
+        (status = 200, description = "The emitter started", body = api::Emitter),
+        (status = 400, description = "Invalid parameters", body = api::Error),
+        (status = 404, description = "No such collection or clip", body = api::Error),
+    )
+)]
+async fn start_emitter(
+    Path(coll_id): Path<u64>,
+    Json(body): Json<api::EmitterParams>,
+    Extension(library): Extension<Arc<RwLock<Library>>>,
+    Extension(emitters): Extension<Arc<Emitters>>,
+) -> Result<Json<api::Emitter>, ApiError> {
+    info!("Start emitter on collection {coll_id}");
+    let kind = library
+        .read()
+        .await
+        .collection(coll_id)
+        .ok_or(PlayerError::NotFound)?
+        .kind;
+    if kind.is_exclusive() {
+        // each firing would cut off the last one
+        return Err(ApiError::bad_request(
+            "Emitters can't play from exclusive collections",
+        ));
+    }
+
+    let settings = body
+        .into_settings(coll_id)
+        .ok_or_else(|| ApiError::bad_request("Invalid emitter settings"))?;
+    let id = emitters.start(settings.clone()).await?;
+
+    Ok(Json(api::Emitter::new(id, settings)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/emitter/{id}/stop",
+    params(
+        ("id" = u64, Path, description = "Emitter id"),
+    ),
+    responses(
+        (status = 200, description = "Done", body = api::Status),
+        (status = 404, description = "No such emitter", body = api::Error),
+    )
+)]
+async fn stop_emitter(
+    Path(id): Path<u64>,
+    Extension(emitters): Extension<Arc<Emitters>>,
+) -> Result<Json<api::Status>, ApiError> {
+    info!("Stop emitter {id}");
+    if !emitters.stop(id).await {
+        return Err(ApiError::not_found("No such emitter"));
+    }
+
+    Ok(Json(api::Status::new("stopped")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/collection/{coll_id}/clip/{clip_id}/playback",
+    params(
+        ("coll_id" = u64, Path, description = "Collection id"),
+        ("clip_id" = u64, Path, description = "Clip id"),
+    ),
+    request_body = api::ClipPlaybackParams,
+    responses(
+        (status = 200, description = "Done", body = api::Status),
+        (status = 400, description = "Invalid parameters", body = api::Error),
+    )
+)]
+async fn clip_playback(
+    Path((coll_id, clip_id)): Path<(u64, u64)>,
+    Json(body): Json<api::ClipPlaybackParams>,
+    Extension(player_mutex): Extension<Arc<Mutex<Player>>>,
+) -> Result<Json<api::Status>, ApiError> {
+    info!("Set clip playback params {coll_id}/{clip_id}");
+    let placement = body
+        .placement()
+        .ok_or_else(|| ApiError::bad_request("Give a pan or a position"))?;
+    if !placement.is_valid() {
+        return Err(ApiError::bad_request("Invalid pan or position"));
+    }
+    let mut player = player_mutex.lock().await;
+    player.set_placement(coll_id, clip_id, placement)?;
+
+    Ok(Json(api::Status::new("ok")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/collection/{coll_id}/effect/{index}",
+    params(
+        ("coll_id" = u64, Path, description = "Collection id"),
+        ("index" = usize, Path, description = "Position in the effect chain"),
+    ),
+    request_body = api::EffectParams,
+    responses(
+        (status = 200, description = "Done", body = api::Status),
+        (status = 400, description = "Invalid parameters", body = api::Error),
+        (status = 404, description = "No such collection or effect", body = api::Error),
+    )
+)]
+async fn coll_effect(
+    Path((coll_id, index)): Path<(u64, usize)>,
+    Json(body): Json<api::EffectParams>,
+    Extension(player_mutex): Extension<Arc<Mutex<Player>>>,
+) -> Result<Json<api::Status>, ApiError> {
+    info!("Set effect {index} on collection {coll_id}");
+    set_effect(BusId::Collection(coll_id), index, body, &player_mutex).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/bus/{kind}/effect/{index}",
+    params(
+        ("kind" = model::CollectionKind, Path, description = "Collection kind"),
+        ("index" = usize, Path, description = "Position in the effect chain"),
+    ),
+    request_body = api::EffectParams,
+    responses(
+        (status = 200, description = "Done", body = api::Status),
+        (status = 400, description = "Invalid parameters", body = api::Error),
+        (status = 404, description = "No such effect", body = api::Error),
+    )
+)]
+async fn kind_effect(
+    Path((kind, index)): Path<(CollectionKind, usize)>,
+    Json(body): Json<api::EffectParams>,
+    Extension(player_mutex): Extension<Arc<Mutex<Player>>>,
+) -> Result<Json<api::Status>, ApiError> {
+    info!("Set effect {index} on {kind:?} bus");
+    set_effect(BusId::Kind(kind), index, body, &player_mutex).await
+}
+
+async fn set_effect(
+    bus_id: BusId,
+    index: usize,
+    body: api::EffectParams,
+    player_mutex: &Mutex<Player>,
+) -> Result<Json<api::Status>, ApiError> {
+    let change = body
+        .into_change()
+        .ok_or_else(|| ApiError::bad_request("Invalid effect parameters"))?;
+    let mut player = player_mutex.lock().await;
+    player.set_effect(bus_id, index, &change)?;
+
+    Ok(Json(api::Status::new("ok")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/recording",
+    responses(
+        (status = 200, description = "The current recording, if any", body = api::Recording),
+    )
+)]
+async fn recording(Extension(recorder): Extension<Arc<Recorder>>) -> Json<api::Recording> {
+    Json(api::Recording::new(
+        recorder.recording(),
+        recorder.dropped_frames(),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/recording/start",
+    responses(
+        (status = 200, description = "The new recording", body = api::Recording),
+        (status = 409, description = "Already recording", body = api::Error),
+    )
+)]
+async fn start_recording(
+    Extension(recorder): Extension<Arc<Recorder>>,
+) -> Result<Json<api::Recording>, ApiError> {
+    info!("Start recording");
+    let path = recorder.start()?;
+
+    Ok(Json(api::Recording::new(Some(path), 0)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/recording/stop",
+    responses(
+        (status = 200, description = "Done", body = api::Status),
+        (status = 409, description = "Not recording", body = api::Error),
+    )
+)]
+async fn stop_recording(
+    Extension(recorder): Extension<Arc<Recorder>>,
+) -> Result<Json<api::Status>, ApiError> {
+    info!("Stop recording");
+    recorder.stop()?;
+
+    Ok(Json(api::Status::new("stopped")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/output",
+    responses(
+        (status = 200, description = "Every output", body = [api::Output]),
+    )
+)]
+async fn outputs(Extension(output_controls): Extension<OutputControls>) -> Json<Vec<api::Output>> {
+    let outputs = output_controls
+        .list()
+        .into_iter()
+        .map(|status| status.into())
+        .collect();
+
+    Json(outputs)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/output/{name}/gain",
+    params(
+        ("name" = String, Path, description = "Output name"),
+    ),
+    request_body = api::PlaybackParams,
+    responses(
+        (status = 200, description = "Done", body = api::Status),
+        (status = 400, description = "Invalid parameters", body = api::Error),
+        (status = 404, description = "No such output", body = api::Error),
+    )
+)]
+async fn output_gain(
+    Path(name): Path<String>,
+    Json(body): Json<api::PlaybackParams>,
+    Extension(output_controls): Extension<OutputControls>,
+) -> Result<Json<api::Status>, ApiError> {
+    info!("Set output gain {name}");
+    if !output_controls.set_gain(&name, check_gain(body.gain)? as f32) {
+        return Err(ApiError::not_found("No such output"));
+    }
+
+    Ok(Json(api::Status::new("ok")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/stream.mp3",
+    responses(
+        (status = 200, description = "The live mix, as an endless mp3", content_type = "audio/mpeg"),
+        (status = 404, description = "Streaming is off", body = api::Error),
+    )
+)]
+async fn stream(
+    Extension(stream): Extension<Option<LiveStream>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let stream = stream.ok_or_else(|| ApiError::not_found("Streaming is off"))?;
+    info!("Stream listener joined");
+
+    // A listener that falls behind just skips ahead.
+    let chunks = tokio_stream::wrappers::BroadcastStream::new(stream.subscribe())
+        .filter_map(|chunk| async move { chunk.ok().map(Ok::<_, std::convert::Infallible>) });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "audio/mpeg"),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        StreamBody::new(chunks),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/events",
+    params(
+        ("Last-Event-ID" = Option<u64>, Header, description = "The last event seen, to pick up after"),
+    ),
+    responses(
+        (status = 200, description = "Server-sent player events, or a resync with the current state", content_type = "text/event-stream"),
+    )
+)]
 async fn events(
-    Extension(player_event_broadcast): Extension<Sender<PlayerEvent>>,
-) -> Sse<impl Stream<Item = Result<Event, Box<dyn std::error::Error + Send + Sync>>>> {
-    // use async_stream::stream;
-
-    let rx = player_event_broadcast.subscribe();
-    let s = tokio_stream::wrappers::BroadcastStream::new(rx).map(|ev_res| match ev_res {
-        Ok(ev) => match Event::default().json_data(api::PlayerEvent::from(ev)) {
-            Ok(e) => Ok(e),
-            Err(e) => {
-                let e: Box<dyn std::error::Error + Send + Sync> = Box::new(e);
-                Err(e)
-            }
-        },
-        Err(e) => {
-            let e: Box<dyn std::error::Error + Send + Sync> = Box::new(e);
-            Err(e)
-        }
+    headers: HeaderMap,
+    Extension(player_mutex): Extension<Arc<Mutex<Player>>>,
+    Extension(event_bus): Extension<EventBus>,
+    Extension(output_controls): Extension<OutputControls>,
+    Extension(access): Extension<Access>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    // A browser coming back says where it left off.
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse().ok());
+
+    let s = client_updates(
+        last_event_id,
+        player_mutex,
+        event_bus,
+        output_controls,
+        access,
+    )
+    .map(|update| match update {
+        Update::Event(ev) => player_event(ev),
+        // The event id is the state's sequence number, so the browser
+        // picks up from there if it reconnects.
+        Update::Resync(state) => Event::default()
+            .event("resync")
+            .id(state.seq.to_string())
+            .json_data(state),
     });
 
     Sse::new(s).keep_alive(KeepAlive::default())
 }
+
+fn player_event(ev: Sequenced) -> Result<Event, axum::Error> {
+    // The event id is its sequence number
+    Event::default()
+        .id(ev.seq.to_string())
+        .json_data(api::PlayerEvent::from(ev.event))
+}
+
+/// What every handler fails with: a status, and a JSON body saying what
+/// went wrong.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    error: api::Error,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError {
+            status,
+            error: api::Error {
+                code,
+                message: message.into(),
+                details: None,
+            },
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::BAD_REQUEST, "bad_request", message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::UNAUTHORIZED, "unauthorized", message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::FORBIDDEN, "forbidden", message)
+    }
+
+    pub fn too_many_requests(message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::TOO_MANY_REQUESTS, "too_many_requests", message)
+    }
+
+    pub fn with_details(mut self, details: impl std::fmt::Display) -> ApiError {
+        self.error.details = Some(details.to_string());
+        self
+    }
+
+    // Anything the client can't do much about.
+    pub fn internal(e: &dyn std::error::Error) -> ApiError {
+        error!(err = %e, "Internal error");
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal",
+            "Internal error",
+        )
+        .with_details(e)
+    }
+}
+
+impl ApiError {
+    /// Just the body, for when there's no http response to put it in.
+    pub fn into_error(self) -> api::Error {
+        self.error
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.error)).into_response()
+    }
+}
+
+impl From<PlayerError> for ApiError {
+    fn from(e: PlayerError) -> Self {
+        match &e {
+            PlayerError::NotFound => ApiError::not_found(e.to_string()),
+            PlayerError::NoFreeVoice => {
+                ApiError::new(StatusCode::CONFLICT, "no_free_voice", e.to_string())
+            }
+            PlayerError::FromFileError(from_file) => match from_file {
+                FromFileError::IoError(_) => ApiError::internal(&e),
+                FromFileError::UnsupportedChannelConfiguration
+                | FromFileError::SymphoniaError(symphonia::core::errors::Error::Unsupported(_)) => {
+                    ApiError::new(
+                        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                        "unsupported_format",
+                        "The clip's audio format isn't supported",
+                    )
+                    .with_details(from_file)
+                }
+                _ => ApiError::new(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "decode_failed",
+                    "The clip's audio couldn't be decoded",
+                )
+                .with_details(from_file),
+            },
+            _ => ApiError::internal(&e),
+        }
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(e: JsonRejection) -> Self {
+        ApiError::bad_request("Invalid request body").with_details(e)
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(e: PathRejection) -> Self {
+        ApiError::bad_request("Invalid path").with_details(e)
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(e: QueryRejection) -> Self {
+        ApiError::bad_request("Invalid query").with_details(e)
+    }
+}
+
+impl From<RecorderError> for ApiError {
+    fn from(e: RecorderError) -> Self {
+        match &e {
+            RecorderError::AlreadyRecording => {
+                ApiError::new(StatusCode::CONFLICT, "already_recording", e.to_string())
+            }
+            RecorderError::NotRecording => {
+                ApiError::new(StatusCode::CONFLICT, "not_recording", e.to_string())
+            }
+            _ => ApiError::internal(&e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ByteRange;
+
+    #[test]
+    fn parses_byte_ranges() {
+        let parse = |range| ByteRange::parse(range, 100);
+
+        assert_eq!(parse("bytes=10-19"), ByteRange::Part(10, 20));
+        assert_eq!(parse("bytes=90-200"), ByteRange::Part(90, 100));
+        // Open ended
+        assert_eq!(parse("bytes=10-"), ByteRange::Part(10, 100));
+        // Suffix
+        assert_eq!(parse("bytes=-10"), ByteRange::Part(90, 100));
+        assert_eq!(parse("bytes=-200"), ByteRange::Part(0, 100));
+        // Out of range
+        assert_eq!(parse("bytes=100-"), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=150-160"), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=-0"), ByteRange::Unsatisfiable);
+        // Not understood, so ignored
+        assert_eq!(parse("bytes=0-9,20-29"), ByteRange::Whole);
+        assert_eq!(parse("bytes=5-3"), ByteRange::Whole);
+        assert_eq!(parse("bytes=a-b"), ByteRange::Whole);
+        assert_eq!(parse("lines=1-2"), ByteRange::Whole);
+    }
+}
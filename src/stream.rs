@@ -0,0 +1,141 @@
+use std::sync::mpsc;
+
+use axum::body::Bytes;
+use kira::dsp::Frame;
+use mp3lame_encoder::{Birtate, Builder, InterleavedPcm};
+use thiserror::Error;
+use tokio::sync::broadcast;
+use tracing::error;
+
+use crate::output::Sink;
+
+/// The mix, encoded as mp3 for anyone who wants to listen over http.
+#[derive(Clone)]
+pub struct LiveStream {
+    chunks: broadcast::Sender<Bytes>,
+}
+
+impl LiveStream {
+    /// Chunks of mp3, from now on. A listener which falls behind
+    /// misses some.
+    pub fn subscribe(&self) -> broadcast::Receiver<Bytes> {
+        self.chunks.subscribe()
+    }
+}
+
+/// The output which feeds a `LiveStream`.
+pub struct StreamSink {
+    frames: mpsc::SyncSender<Vec<Frame>>,
+    chunks: broadcast::Sender<Bytes>,
+}
+
+impl Sink for StreamSink {
+    fn write(&mut self, frames: &[Frame]) {
+        // Don't bother encoding for nobody.
+        if self.chunks.receiver_count() == 0 {
+            return;
+        }
+        // If the encoder can't keep up, these are lost.
+        let _ = self.frames.try_send(frames.to_vec());
+    }
+}
+
+/// Make a live stream, and the output to feed it with. `bitrate` is in
+/// kbps.
+pub fn live_stream(
+    sample_rate: u32,
+    bitrate: u32,
+) -> Result<(LiveStream, StreamSink), StreamError> {
+    let mut builder = Builder::new().ok_or(StreamError::Encoder)?;
+    builder
+        .set_num_channels(2)
+        .map_err(|_| StreamError::Encoder)?;
+    builder
+        .set_sample_rate(sample_rate)
+        .map_err(|_| StreamError::SampleRate(sample_rate))?;
+    builder
+        .set_brate(lame_bitrate(bitrate).ok_or(StreamError::Bitrate(bitrate))?)
+        .map_err(|_| StreamError::Bitrate(bitrate))?;
+    let mut encoder = builder.build().map_err(|_| StreamError::Encoder)?;
+
+    // About a second's worth of batches.
+    let (frames_tx, frames_rx) = mpsc::sync_channel::<Vec<Frame>>(100);
+    let (chunks, _) = broadcast::channel(64);
+
+    let chunks_for_encoder = chunks.clone();
+    std::thread::spawn(move || {
+        let mut pcm = vec![];
+        let mut mp3 = vec![];
+        for frames in frames_rx {
+            pcm.clear();
+            for frame in frames {
+                for sample in [frame.left, frame.right] {
+                    pcm.push((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16);
+                }
+            }
+
+            mp3.clear();
+            mp3.reserve(mp3lame_encoder::max_required_buffer_size(pcm.len() / 2));
+            match encoder.encode(InterleavedPcm(&pcm), mp3.spare_capacity_mut()) {
+                Ok(len) => {
+                    // SAFETY: the encoder has just written this many bytes
+                    unsafe { mp3.set_len(len) };
+                }
+                Err(e) => {
+                    error!("Error encoding stream: {e:?}");
+                    continue;
+                }
+            }
+
+            // The encoder may be holding on to these frames until it
+            // has enough for an mp3 frame.
+            if !mp3.is_empty() {
+                let _ = chunks_for_encoder.send(Bytes::copy_from_slice(&mp3));
+            }
+        }
+    });
+
+    Ok((
+        LiveStream {
+            chunks: chunks.clone(),
+        },
+        StreamSink {
+            frames: frames_tx,
+            chunks,
+        },
+    ))
+}
+
+fn lame_bitrate(kbps: u32) -> Option<Birtate> {
+    Some(match kbps {
+        8 => Birtate::Kbps8,
+        16 => Birtate::Kbps16,
+        24 => Birtate::Kbps24,
+        32 => Birtate::Kbps32,
+        40 => Birtate::Kbps40,
+        48 => Birtate::Kbps48,
+        64 => Birtate::Kbps64,
+        80 => Birtate::Kbps80,
+        96 => Birtate::Kbps96,
+        112 => Birtate::Kbps112,
+        128 => Birtate::Kbps128,
+        160 => Birtate::Kbps160,
+        192 => Birtate::Kbps192,
+        224 => Birtate::Kbps224,
+        256 => Birtate::Kbps256,
+        320 => Birtate::Kbps320,
+        _ => return None,
+    })
+}
+
+#[derive(Error, Debug)]
+pub enum StreamError {
+    #[error("Couldn't set up the mp3 encoder")]
+    Encoder,
+
+    #[error("Can't stream mp3 at a sample rate of {0}")]
+    SampleRate(u32),
+
+    #[error("Can't stream mp3 at {0} kbps")]
+    Bitrate(u32),
+}
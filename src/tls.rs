@@ -0,0 +1,61 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+use tracing::info;
+
+/// The certificate and private key to serve https with, both PEM.
+#[derive(Clone, Debug)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl TlsFiles {
+    /// Make a self-signed certificate for `names` (host names or ip
+    /// addresses), unless there's a certificate already. Browsers will
+    /// warn about it once; after that, it's the same certificate every
+    /// time. Having only one of the two files is an error, rather than
+    /// a reason to replace it.
+    pub fn ensure_self_signed(&self, names: Vec<String>) -> Result<(), TlsError> {
+        match (self.cert.exists(), self.key.exists()) {
+            (true, true) => return Ok(()),
+            (false, false) => (),
+            (true, false) => return Err(TlsError::Missing(self.key.clone())),
+            (false, true) => return Err(TlsError::Missing(self.cert.clone())),
+        }
+
+        info!("Making a self-signed certificate for {}", names.join(", "));
+        let cert = rcgen::generate_simple_self_signed(names)?;
+        write_new(&self.cert, 0o644, &cert.serialize_pem()?)?;
+        // Only we should be able to read the key.
+        write_new(&self.key, 0o600, &cert.serialize_private_key_pem())?;
+        Ok(())
+    }
+}
+
+// Fails if something's already there, rather than clobbering it.
+fn write_new(path: &Path, mode: u32, contents: &str) -> std::io::Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(path)?
+        .write_all(contents.as_bytes())
+}
+
+#[derive(Error, Debug)]
+pub enum TlsError {
+    #[error("{0:?} is missing, but its partner is there; give both or neither")]
+    Missing(PathBuf),
+
+    #[error("Couldn't make a certificate: {0}")]
+    Generate(#[from] rcgen::RcgenError),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
@@ -0,0 +1,261 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    response::Response,
+    Extension,
+};
+use futures::StreamExt;
+use serde::Serialize;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{error, info};
+
+use crate::{
+    api,
+    auth::Access,
+    events::{EventBus, Update},
+    model::Library,
+    output::OutputControls,
+    player::{PlayOptions, Player, PlayerError},
+    server::{check_gain, client_updates, ApiError},
+};
+
+/// Everything a socket can act on.
+#[derive(Clone)]
+struct Context {
+    library: Arc<RwLock<Library>>,
+    player: Arc<Mutex<Player>>,
+    output_controls: OutputControls,
+    access: Access,
+}
+
+/// A websocket carrying the same commands as the http api, and the same
+/// events as /events.
+pub async fn ws(
+    upgrade: WebSocketUpgrade,
+    Extension(library): Extension<Arc<RwLock<Library>>>,
+    Extension(player): Extension<Arc<Mutex<Player>>>,
+    Extension(event_bus): Extension<EventBus>,
+    Extension(output_controls): Extension<OutputControls>,
+    Extension(access): Extension<Access>,
+) -> Response {
+    let context = Context {
+        library,
+        player,
+        output_controls,
+        access,
+    };
+    upgrade.on_upgrade(move |socket| serve(socket, context, event_bus))
+}
+
+async fn serve(mut socket: WebSocket, context: Context, event_bus: EventBus) {
+    info!("Websocket client connected");
+    let events = client_updates(
+        None,
+        context.player.clone(),
+        event_bus,
+        context.output_controls.clone(),
+        context.access.clone(),
+    );
+    tokio::pin!(events);
+
+    loop {
+        let message = tokio::select! {
+            received = socket.recv() => match received {
+                Some(Ok(Message::Text(text))) => reply(&context, &text).await,
+                // Pings are answered for us
+                Some(Ok(Message::Binary(_) | Message::Ping(_) | Message::Pong(_))) => continue,
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+            },
+            update = events.next() => match update {
+                Some(Update::Event(sequenced)) => api::WsMessage::Event {
+                    seq: sequenced.seq,
+                    event: sequenced.event.into(),
+                },
+                Some(Update::Resync(state)) => api::WsMessage::Resync { state },
+                None => break,
+            },
+        };
+
+        if !send(&mut socket, &message).await {
+            break;
+        }
+    }
+
+    info!("Websocket client disconnected");
+}
+
+async fn send(socket: &mut WebSocket, message: &api::WsMessage) -> bool {
+    let text = match serde_json::to_string(message) {
+        Ok(text) => text,
+        Err(e) => {
+            error!(err = %&e as &dyn std::error::Error, "Error serializing websocket message");
+            return true;
+        }
+    };
+
+    socket.send(Message::Text(text)).await.is_ok()
+}
+
+async fn reply(context: &Context, text: &str) -> api::WsMessage {
+    // Get at the id first, so even a bad request's reply can carry it.
+    let (id, request) = match serde_json::from_str::<serde_json::Value>(text) {
+        Ok(value) => {
+            let id = value.get("id").and_then(|id| id.as_str()).map(String::from);
+            (id, serde_json::from_value::<api::WsRequest>(value))
+        }
+        Err(e) => (None, Err(e)),
+    };
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => {
+            return api::WsMessage::Reply {
+                id,
+                result: api::WsResult::Error(
+                    ApiError::bad_request("Invalid request")
+                        .with_details(e)
+                        .into_error(),
+                ),
+            }
+        }
+    };
+
+    let result = match run(context, request.command).await {
+        Ok(result) => api::WsResult::Ok(result),
+        Err(e) => api::WsResult::Error(e.into_error()),
+    };
+
+    api::WsMessage::Reply {
+        id: request.id,
+        result,
+    }
+}
+
+async fn run(context: &Context, command: api::WsCommand) -> Result<serde_json::Value, ApiError> {
+    info!("Websocket command {command:?}");
+    if !allowed(&context.access, &command) {
+        return Err(ApiError::forbidden("Not allowed for players"));
+    }
+
+    match command {
+        api::WsCommand::Play {
+            coll_id,
+            clip_id,
+            options,
+        } => {
+            let library = context.library.read().await;
+            let coll = library
+                .collection(parse_id(&coll_id)?)
+                .ok_or(PlayerError::NotFound)?;
+            let clip = coll
+                .clip(parse_id(&clip_id)?)
+                .ok_or(PlayerError::NotFound)?;
+            let options = options
+                .into_options(PlayOptions::default())
+                .ok_or_else(|| ApiError::bad_request("Invalid play options"))?;
+
+            let instance = context.player.lock().await.play_clip(coll, clip, options)?;
+            json(api::Instance::from(instance))
+        }
+        api::WsCommand::Stop {
+            coll_id,
+            clip_id,
+            fade,
+        } => {
+            let options = stop_options(fade)?;
+            let mut player = context.player.lock().await;
+            player.stop_clip(parse_id(&coll_id)?, parse_id(&clip_id)?, &options)?;
+            json(api::Status::new("stopped"))
+        }
+        api::WsCommand::StopInstance { instance_id, fade } => {
+            let options = stop_options(fade)?;
+            let mut player = context.player.lock().await;
+            player.stop_instance(parse_id(&instance_id)?, &options)?;
+            json(api::Status::new("stopped"))
+        }
+        api::WsCommand::StopCollection { coll_id, fade } => {
+            let options = stop_options(fade)?;
+            let mut player = context.player.lock().await;
+            player.stop_coll(parse_id(&coll_id)?, &options)?;
+            json(api::Status::new("stopped"))
+        }
+        api::WsCommand::StopAll { fade } => {
+            let options = stop_options(fade)?;
+            context.player.lock().await.stop_all(&options)?;
+            json(api::Status::new("stopped"))
+        }
+        api::WsCommand::SetGain { coll_id, gain } => {
+            let mut player = context.player.lock().await;
+            player.set_gain(parse_id(&coll_id)?, check_gain(gain)?)?;
+            json(api::Status::new("ok"))
+        }
+        api::WsCommand::SetIntensity {
+            coll_id,
+            clip_id,
+            intensity,
+        } => {
+            let mut player = context.player.lock().await;
+            player.set_intensity(parse_id(&coll_id)?, parse_id(&clip_id)?, intensity)?;
+            json(api::Status::new("ok"))
+        }
+        api::WsCommand::SetOutputGain { name, gain } => {
+            if !context
+                .output_controls
+                .set_gain(&name, check_gain(gain)? as f32)
+            {
+                return Err(ApiError::not_found("No such output"));
+            }
+            json(api::Status::new("ok"))
+        }
+        api::WsCommand::Playing => {
+            let playing = context.player.lock().await.playing_instances();
+            json(
+                playing
+                    .into_iter()
+                    .filter(|(_, coll_id, _)| context.access.may_control(*coll_id))
+                    .map(|(instance_id, coll_id, clip_id)| {
+                        (
+                            coll_id.to_string(),
+                            clip_id.to_string(),
+                            instance_id.to_string(),
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        }
+    }
+}
+
+// The same as over http: players can only play and stop clips in their
+// collections.
+fn allowed(access: &Access, command: &api::WsCommand) -> bool {
+    match (access, command) {
+        (Access::Gm, _) => true,
+        (
+            Access::Player(_),
+            api::WsCommand::Play { coll_id, .. }
+            | api::WsCommand::Stop { coll_id, .. }
+            | api::WsCommand::StopCollection { coll_id, .. },
+        ) => parse_id(coll_id)
+            .map(|coll_id| access.may_control(coll_id))
+            .unwrap_or(false),
+        (Access::Player(_), api::WsCommand::Playing) => true,
+        (Access::Player(_), _) => false,
+    }
+}
+
+// Ids are strings in the api, as javascript can't hold a u64.
+fn parse_id(id: &str) -> Result<u64, ApiError> {
+    id.parse()
+        .map_err(|e| ApiError::bad_request("Invalid id").with_details(e))
+}
+
+fn stop_options(params: api::StopParams) -> Result<crate::player::StopOptions, ApiError> {
+    params
+        .into_options()
+        .ok_or_else(|| ApiError::bad_request("Invalid fade"))
+}
+
+fn json(value: impl Serialize) -> Result<serde_json::Value, ApiError> {
+    serde_json::to_value(value).map_err(|e| ApiError::internal(&e))
+}